@@ -0,0 +1,17 @@
+use std::{env, fs, path::Path};
+
+/// Writes the number of the newest file in `migrations` for `SCHEMA_VERSION`, `014_migration.sql` is 14.
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+    let version = fs::read_dir("migrations")
+        .expect("Failed to read migrations")
+        .filter_map(|entry| {
+            let name = entry.expect("Failed to read migrations").file_name();
+            name.to_str()?.split_once('_')?.0.parse::<i32>().ok()
+        })
+        .max()
+        .unwrap_or(0);
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is not set");
+    fs::write(Path::new(&out_dir).join("schema_version.rs"), version.to_string())
+        .expect("Failed to write schema_version.rs");
+}
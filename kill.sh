@@ -6,7 +6,7 @@ if [ ${#PIDS[@]} -gt 0 ];then
     for i in "${PIDS[@]}"
         do
            echo "Killing process with PID - $i"
-           kill -9 $i
+           kill $i
            PID_KILLED=1
         done
 fi
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE short_url ADD COLUMN IF NOT EXISTS expiry_date TIMESTAMPTZ;
\ No newline at end of file
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS click_event(
+    id BIGSERIAL PRIMARY KEY,
+    short_url_id INT NOT NULL REFERENCES short_url(id) ON DELETE CASCADE,
+    short_url TEXT NOT NULL,
+    clicked_on TIMESTAMPTZ NOT NULL,
+    referrer TEXT,
+    user_agent TEXT,
+    accept_language TEXT,
+    ip_hash TEXT
+);
+
+CREATE INDEX IF NOT EXISTS click_event_short_url_id_clicked_on_idx ON click_event (short_url_id, clicked_on);
\ No newline at end of file
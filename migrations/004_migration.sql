@@ -0,0 +1,3 @@
+ALTER TABLE click_event ADD COLUMN IF NOT EXISTS browser TEXT;
+
+ALTER TABLE click_event ADD COLUMN IF NOT EXISTS os TEXT;
\ No newline at end of file
@@ -0,0 +1,8 @@
+CREATE TYPE "redirect_type" AS ENUM (
+  'moved_permanently',
+  'found',
+  'temporary_redirect',
+  'permanent_redirect'
+);
+
+ALTER TABLE short_url ADD COLUMN IF NOT EXISTS redirect_type redirect_type;
\ No newline at end of file
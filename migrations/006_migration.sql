@@ -0,0 +1,13 @@
+CREATE TYPE "domain_rule_type" AS ENUM (
+  'block',
+  'allow'
+);
+
+CREATE TABLE IF NOT EXISTS domain_rule(
+    id SERIAL PRIMARY KEY,
+    pattern TEXT NOT NULL,
+    rule_type domain_rule_type NOT NULL,
+    created_on TIMESTAMPTZ NOT NULL,
+    created_by uuid NOT NULL,
+    UNIQUE (pattern, rule_type)
+);
\ No newline at end of file
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS api_key(
+    id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES user_account(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    key_prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    created_on TIMESTAMPTZ NOT NULL,
+    last_used_on TIMESTAMPTZ,
+    expiry_date TIMESTAMPTZ,
+    revoked_on TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS api_key_user_id_idx ON api_key (user_id);
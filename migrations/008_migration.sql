@@ -0,0 +1,5 @@
+ALTER TABLE user_account ADD COLUMN IF NOT EXISTS is_active BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE user_account ADD COLUMN IF NOT EXISTS created_on TIMESTAMPTZ NOT NULL DEFAULT now();
+ALTER TABLE user_account ADD COLUMN IF NOT EXISTS deactivated_on TIMESTAMPTZ;
+
+CREATE UNIQUE INDEX IF NOT EXISTS user_account_username_key ON user_account (username);
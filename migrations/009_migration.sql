@@ -0,0 +1,7 @@
+CREATE TYPE "user_role" AS ENUM (
+  'read_only',
+  'member',
+  'admin'
+);
+
+ALTER TABLE user_account ADD COLUMN IF NOT EXISTS role user_role NOT NULL DEFAULT 'member';
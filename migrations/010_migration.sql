@@ -0,0 +1,33 @@
+CREATE TABLE IF NOT EXISTS organization(
+    id uuid PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    default_expiry_days INTEGER,
+    allowed_domains TEXT[] NOT NULL DEFAULT '{}',
+    short_url_length INTEGER,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+ALTER TABLE user_account ADD COLUMN IF NOT EXISTS organization_id uuid REFERENCES organization(id);
+
+INSERT INTO organization (id, name)
+SELECT gen_random_uuid(), company_name FROM user_account
+WHERE organization_id IS NULL AND company_name IS NOT NULL
+GROUP BY company_name
+ON CONFLICT (name) DO NOTHING;
+
+UPDATE user_account SET organization_id = organization.id
+FROM organization
+WHERE user_account.organization_id IS NULL AND organization.name = user_account.company_name;
+
+ALTER TABLE user_account ALTER COLUMN organization_id SET NOT NULL;
+ALTER TABLE user_account ALTER COLUMN company_name DROP NOT NULL;
+
+CREATE INDEX IF NOT EXISTS user_account_organization_id_idx ON user_account (organization_id);
+
+ALTER TABLE short_url ADD COLUMN IF NOT EXISTS organization_id uuid REFERENCES organization(id) ON DELETE CASCADE;
+
+UPDATE short_url SET organization_id = user_account.organization_id
+FROM user_account
+WHERE short_url.organization_id IS NULL AND user_account.id = short_url.user_id;
+
+CREATE INDEX IF NOT EXISTS short_url_organization_id_idx ON short_url (organization_id, created_on);
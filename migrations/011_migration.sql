@@ -0,0 +1,15 @@
+ALTER TABLE user_account ADD COLUMN IF NOT EXISTS token_version INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS refresh_token(
+    id uuid PRIMARY KEY,
+    user_id uuid NOT NULL REFERENCES user_account(id) ON DELETE CASCADE,
+    family_id uuid NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_on TIMESTAMPTZ NOT NULL,
+    expiry_date TIMESTAMPTZ NOT NULL,
+    used_on TIMESTAMPTZ,
+    revoked_on TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS refresh_token_user_id_idx ON refresh_token (user_id);
+CREATE INDEX IF NOT EXISTS refresh_token_family_id_idx ON refresh_token (family_id);
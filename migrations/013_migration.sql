@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS link_quota(
+    id uuid PRIMARY KEY,
+    organization_id uuid UNIQUE REFERENCES organization(id) ON DELETE CASCADE,
+    user_id uuid UNIQUE REFERENCES user_account(id) ON DELETE CASCADE,
+    max_active_links INTEGER,
+    max_links_per_day INTEGER,
+    max_links_per_month INTEGER,
+    updated_on TIMESTAMPTZ NOT NULL,
+    CHECK ((organization_id IS NULL) <> (user_id IS NULL))
+);
+
+CREATE INDEX IF NOT EXISTS short_url_user_id_idx ON short_url (user_id, created_on);
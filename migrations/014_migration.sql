@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS schema_migration(
+    version INT PRIMARY KEY,
+    applied_on TIMESTAMPTZ NOT NULL
+);
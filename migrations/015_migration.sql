@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS link_creation(
+    short_url_id INT PRIMARY KEY,
+    user_id uuid NOT NULL,
+    organization_id uuid,
+    created_on TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS link_creation_user_id_idx ON link_creation (user_id, created_on);
+CREATE INDEX IF NOT EXISTS link_creation_organization_id_idx ON link_creation (organization_id, created_on);
+CREATE INDEX IF NOT EXISTS link_creation_created_on_idx ON link_creation (created_on);
+
+INSERT INTO link_creation (short_url_id, user_id, organization_id, created_on)
+SELECT id, user_id, organization_id, created_on FROM short_url
+WHERE created_on >= now() - INTERVAL '32 days'
+ON CONFLICT (short_url_id) DO NOTHING;
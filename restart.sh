@@ -8,7 +8,8 @@ if [ ${#PIDS[@]} -gt 0 ];then
     for i in "${PIDS[@]}"
         do
            echo "Killing process with PID - $i"
-           kill -9 $i
+           kill $i
+           while kill -0 $i 2>/dev/null; do sleep 1; done
            PID_KILLED=1
         done
 fi
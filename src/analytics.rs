@@ -0,0 +1,332 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::models::ShortUrlModel;
+use crate::schemas::{AnalyticsSettings, ClickBucket, StatCount, StatsInterval};
+use crate::utils::{client_ip, generate_short_url};
+
+#[derive(Debug)]
+pub struct ClickEvent {
+    pub short_url_id: i32,
+    pub short_url: String,
+    pub clicked_on: DateTime<Utc>,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    pub accept_language: Option<String>,
+    pub ip_hash: Option<String>,
+    pub browser: Option<String>,
+    pub os: Option<String>,
+}
+
+impl ClickEvent {
+    pub fn from_request(
+        url: &ShortUrlModel,
+        request: &HttpRequest,
+        ip_hash_salt: Option<&SecretString>,
+        trusted_proxies: &[IpAddr],
+    ) -> Self {
+        let header = |name: actix_web::http::header::HeaderName| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let client_ip = client_ip(request.peer_addr(), request.headers(), trusted_proxies);
+        let user_agent = header(actix_web::http::header::USER_AGENT);
+        Self {
+            short_url_id: url.id,
+            short_url: url.short_url.clone(),
+            clicked_on: Utc::now(),
+            referrer: header(actix_web::http::header::REFERER),
+            browser: user_agent.as_deref().map(|ua| parse_browser(ua).to_string()),
+            os: user_agent.as_deref().map(|ua| parse_os(ua).to_string()),
+            user_agent,
+            accept_language: header(actix_web::http::header::ACCEPT_LANGUAGE),
+            ip_hash: client_ip.zip(ip_hash_salt).map(|(ip, salt)| hash_client_ip(&ip.to_string(), salt)),
+        }
+    }
+}
+
+pub fn parse_browser(user_agent: &str) -> &'static str {
+    let ua = user_agent.to_lowercase();
+    if ["bot", "spider", "crawl"].iter().any(|k| ua.contains(k)) {
+        "Bot"
+    } else if ua.contains("edg/") || ua.contains("edga/") || ua.contains("edgios/") {
+        "Edge"
+    } else if ua.contains("opr/") || ua.contains("opera") {
+        "Opera"
+    } else if ua.contains("samsungbrowser") {
+        "Samsung Internet"
+    } else if ua.contains("firefox") || ua.contains("fxios") {
+        "Firefox"
+    } else if ua.contains("chrome") || ua.contains("crios") {
+        "Chrome"
+    } else if ua.contains("safari") {
+        "Safari"
+    } else if ua.contains("curl") || ua.contains("wget") {
+        "CLI"
+    } else {
+        "Other"
+    }
+}
+
+pub fn parse_os(user_agent: &str) -> &'static str {
+    let ua = user_agent.to_lowercase();
+    if ua.contains("windows") {
+        "Windows"
+    } else if ua.contains("android") {
+        "Android"
+    } else if ua.contains("iphone") || ua.contains("ipad") || ua.contains("ipod") {
+        "iOS"
+    } else if ua.contains("mac os x") || ua.contains("macintosh") {
+        "macOS"
+    } else if ua.contains("cros") {
+        "ChromeOS"
+    } else if ua.contains("linux") {
+        "Linux"
+    } else {
+        "Other"
+    }
+}
+
+/// The configured salt, or a random one with a warning when `SECRET__IP_HASH_SALT` is unset.
+pub fn resolve_ip_hash_salt(configured: Option<SecretString>) -> SecretString {
+    configured
+        .filter(|salt| !salt.expose_secret().is_empty())
+        .unwrap_or_else(|| {
+            tracing::warn!(
+                "SECRET__IP_HASH_SALT is not set, using a random salt. Unique visitors will not be comparable across restarts or instances"
+            );
+            SecretString::from(generate_short_url(32))
+        })
+}
+
+/// Keyed hash so stored values can count unique visitors without being reversible to an IP.
+pub fn hash_client_ip(ip: &str, salt: &SecretString) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(salt.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(ip.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Cheap handle used by the redirect handler, events are written by a background task.
+#[derive(Clone)]
+pub struct ClickRecorder {
+    sender: mpsc::Sender<ClickEvent>,
+}
+
+impl ClickRecorder {
+    pub fn spawn(pool: PgPool, settings: &AnalyticsSettings) -> (Self, JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel(settings.buffer_size);
+        let handle = tokio::spawn(run_click_writer(
+            pool,
+            receiver,
+            settings.batch_size,
+            Duration::from_millis(settings.flush_interval_ms),
+        ));
+        (Self { sender }, handle)
+    }
+
+    pub fn record(&self, event: ClickEvent) {
+        if let Err(e) = self.sender.try_send(event) {
+            tracing::warn!("Dropping click event: {}", e);
+        }
+    }
+}
+
+async fn run_click_writer(
+    pool: PgPool,
+    mut receiver: mpsc::Receiver<ClickEvent>,
+    batch_size: usize,
+    flush_interval: Duration,
+) {
+    let mut buffer = Vec::with_capacity(batch_size);
+    let mut interval = tokio::time::interval(flush_interval);
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Some(event) => {
+                    buffer.push(event);
+                    if buffer.len() >= batch_size {
+                        flush_click_events(&pool, &mut buffer).await;
+                    }
+                }
+                None => {
+                    flush_click_events(&pool, &mut buffer).await;
+                    break;
+                }
+            },
+            _ = interval.tick() => flush_click_events(&pool, &mut buffer).await,
+        }
+    }
+}
+
+#[tracing::instrument(name = "flush_click_events", skip_all, fields(count = buffer.len()))]
+async fn flush_click_events(pool: &PgPool, buffer: &mut Vec<ClickEvent>) {
+    if buffer.is_empty() {
+        return;
+    }
+    let events = std::mem::take(buffer);
+    if let Err(e) = insert_click_events(pool, &events).await {
+        tracing::error!("Failed to write {} click events: {:?}", events.len(), e);
+    }
+}
+
+pub async fn insert_click_events(pool: &PgPool, events: &[ClickEvent]) -> Result<(), anyhow::Error> {
+    let short_url_ids: Vec<i32> = events.iter().map(|e| e.short_url_id).collect();
+    let short_urls: Vec<String> = events.iter().map(|e| e.short_url.clone()).collect();
+    let clicked_on: Vec<DateTime<Utc>> = events.iter().map(|e| e.clicked_on).collect();
+    let referrers: Vec<Option<String>> = events.iter().map(|e| e.referrer.clone()).collect();
+    let user_agents: Vec<Option<String>> = events.iter().map(|e| e.user_agent.clone()).collect();
+    let accept_languages: Vec<Option<String>> = events.iter().map(|e| e.accept_language.clone()).collect();
+    let ip_hashes: Vec<Option<String>> = events.iter().map(|e| e.ip_hash.clone()).collect();
+    let browsers: Vec<Option<String>> = events.iter().map(|e| e.browser.clone()).collect();
+    let oses: Vec<Option<String>> = events.iter().map(|e| e.os.clone()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO click_event (short_url_id, short_url, clicked_on, referrer, user_agent, accept_language, ip_hash, browser, os)
+        SELECT t.short_url_id, t.short_url, t.clicked_on, t.referrer, t.user_agent, t.accept_language, t.ip_hash, t.browser, t.os
+        FROM UNNEST($1::INT4[], $2::TEXT[], $3::TIMESTAMPTZ[], $4::TEXT[], $5::TEXT[], $6::TEXT[], $7::TEXT[], $8::TEXT[], $9::TEXT[])
+            AS t(short_url_id, short_url, clicked_on, referrer, user_agent, accept_language, ip_hash, browser, os)
+        WHERE EXISTS (SELECT 1 FROM short_url WHERE short_url.id = t.short_url_id)
+        "#,
+        &short_url_ids,
+        &short_urls,
+        &clicked_on,
+        &referrers as &[Option<String>],
+        &user_agents as &[Option<String>],
+        &accept_languages as &[Option<String>],
+        &ip_hashes as &[Option<String>],
+        &browsers as &[Option<String>],
+        &oses as &[Option<String>]
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "fetch_click_totals", skip(pool))]
+pub async fn fetch_click_totals(
+    pool: &PgPool,
+    short_url_id: Option<i32>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<(i64, i64), anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT count(*) AS "total_clicks!", count(DISTINCT ip_hash) AS "unique_visitors!"
+        FROM click_event
+        WHERE ($1::INT4 IS NULL OR short_url_id = $1) AND clicked_on >= $2 AND clicked_on < $3"#,
+        short_url_id,
+        from,
+        to
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((row.total_clicks, row.unique_visitors))
+}
+
+#[tracing::instrument(name = "fetch_clicks_over_time", skip(pool))]
+pub async fn fetch_clicks_over_time(
+    pool: &PgPool,
+    short_url_id: Option<i32>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    interval: &StatsInterval,
+) -> Result<Vec<ClickBucket>, anyhow::Error> {
+    let buckets = sqlx::query_as!(
+        ClickBucket,
+        r#"SELECT date_trunc($4, clicked_on, 'UTC') AS "bucket!", count(*) AS "clicks!"
+        FROM click_event
+        WHERE ($1::INT4 IS NULL OR short_url_id = $1) AND clicked_on >= $2 AND clicked_on < $3
+        GROUP BY 1
+        ORDER BY 1"#,
+        short_url_id,
+        from,
+        to,
+        interval.date_trunc_unit()
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(buckets)
+}
+
+#[tracing::instrument(name = "fetch_top_links", skip(pool))]
+pub async fn fetch_top_links(
+    pool: &PgPool,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<StatCount>, anyhow::Error> {
+    let links = sqlx::query_as!(
+        StatCount,
+        r#"SELECT short_url AS "value!", count(*) AS "clicks!"
+        FROM click_event
+        WHERE clicked_on >= $1 AND clicked_on < $2
+        GROUP BY short_url
+        ORDER BY 2 DESC, 1
+        LIMIT $3"#,
+        from,
+        to,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(links)
+}
+
+/// Top values per dimension (referrer, user_agent, browser, os, language) in a single pass over the range.
+#[tracing::instrument(name = "fetch_click_breakdowns", skip(pool))]
+pub async fn fetch_click_breakdowns(
+    pool: &PgPool,
+    short_url_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<(String, StatCount)>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        WITH clicks AS (
+            SELECT referrer, user_agent, browser, os, accept_language
+            FROM click_event
+            WHERE short_url_id = $1 AND clicked_on >= $2 AND clicked_on < $3
+        ),
+        dimensions AS (
+            SELECT 'referrer' AS dimension, COALESCE(referrer, '(direct)') AS value FROM clicks
+            UNION ALL SELECT 'user_agent', COALESCE(user_agent, '(unknown)') FROM clicks
+            UNION ALL SELECT 'browser', COALESCE(browser, 'Other') FROM clicks
+            UNION ALL SELECT 'os', COALESCE(os, 'Other') FROM clicks
+            UNION ALL SELECT 'language', COALESCE(NULLIF(lower(trim(split_part(split_part(accept_language, ',', 1), ';', 1))), ''), '(unknown)') FROM clicks
+        ),
+        counted AS (
+            SELECT dimension, value, count(*) AS clicks,
+                row_number() OVER (PARTITION BY dimension ORDER BY count(*) DESC, value) AS rank
+            FROM dimensions
+            GROUP BY dimension, value
+        )
+        SELECT dimension AS "dimension!", value AS "value!", clicks AS "clicks!"
+        FROM counted
+        WHERE rank <= $4
+        ORDER BY dimension, rank
+        "#,
+        short_url_id,
+        from,
+        to,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.dimension, StatCount { value: row.value, clicks: row.clicks }))
+        .collect())
+}
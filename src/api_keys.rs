@@ -0,0 +1,105 @@
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::models::ApiKeyModel;
+use crate::schemas::{AuthenticatedUser, UserRole};
+
+const API_KEY_PREFIX: &str = "rk_";
+const API_KEY_LENGTH: usize = 40;
+/// Characters of the key kept in clear so users can tell their keys apart.
+const API_KEY_VISIBLE_LENGTH: usize = 8;
+
+/// Returns the full key, to be shown once, and the visible prefix that is stored alongside its hash.
+pub fn generate_api_key() -> (String, String) {
+    let secret: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(API_KEY_LENGTH)
+        .map(char::from)
+        .collect();
+    let key = format!("{}{}", API_KEY_PREFIX, secret);
+    let visible = key[..API_KEY_PREFIX.len() + API_KEY_VISIBLE_LENGTH].to_string();
+    (key, visible)
+}
+
+/// Keys are long random strings, so a plain SHA-256 is enough to make a leaked table useless.
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+#[tracing::instrument(name = "insert_api_key", skip(pool, key_hash))]
+pub async fn insert_api_key(
+    pool: &PgPool,
+    user_id: &Uuid,
+    name: &str,
+    key_prefix: &str,
+    key_hash: &str,
+    expiry_date: Option<DateTime<Utc>>,
+) -> Result<ApiKeyModel, anyhow::Error> {
+    let api_key = sqlx::query_as!(
+        ApiKeyModel,
+        r#"INSERT INTO api_key (id, user_id, name, key_prefix, key_hash, created_on, expiry_date)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, name, key_prefix, created_on, last_used_on, expiry_date, revoked_on"#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        key_prefix,
+        key_hash,
+        Utc::now(),
+        expiry_date
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(api_key)
+}
+
+#[tracing::instrument(name = "list_user_api_keys", skip(pool))]
+pub async fn list_user_api_keys(pool: &PgPool, user_id: &Uuid) -> Result<Vec<ApiKeyModel>, anyhow::Error> {
+    let api_keys = sqlx::query_as!(
+        ApiKeyModel,
+        r#"SELECT id, name, key_prefix, created_on, last_used_on, expiry_date, revoked_on
+        FROM api_key
+        WHERE user_id = $1
+        ORDER BY created_on DESC"#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(api_keys)
+}
+
+/// Returns false when the key does not exist, belongs to someone else or is already revoked.
+#[tracing::instrument(name = "revoke_user_api_key", skip(pool))]
+pub async fn revoke_user_api_key(pool: &PgPool, id: &Uuid, user_id: &Uuid) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        "UPDATE api_key SET revoked_on = $3 WHERE id = $1 AND user_id = $2 AND revoked_on IS NULL",
+        id,
+        user_id,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Looks up the owner of a usable key, and records that the key was used.
+#[tracing::instrument(name = "authenticate_api_key", skip_all)]
+pub async fn authenticate_api_key(pool: &PgPool, key: &str) -> Result<Option<AuthenticatedUser>, anyhow::Error> {
+    let user = sqlx::query_as!(
+        AuthenticatedUser,
+        r#"UPDATE api_key SET last_used_on = $2
+        FROM user_account
+        WHERE api_key.key_hash = $1 AND api_key.revoked_on IS NULL
+            AND (api_key.expiry_date IS NULL OR api_key.expiry_date > $2)
+            AND user_account.id = api_key.user_id AND user_account.is_active
+        RETURNING user_account.id, user_account.organization_id, user_account.role AS "role: UserRole""#,
+        hash_api_key(key),
+        Utc::now()
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(user)
+}
@@ -0,0 +1,190 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use moka::future::Cache;
+use moka::notification::RemovalCause;
+use prometheus::{IntCounterVec, Opts};
+use sqlx::PgPool;
+
+use crate::models::ShortUrlModel;
+use crate::schemas::ApplicationSettings;
+use crate::utils::fetch_short_url;
+
+/// Longest a lookup may take. Invalidations are remembered for the entry TTL plus this, so a
+/// lookup that started before one can never put the old row back.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Lookups raced by an invalidation are retried, after that the row is served uncached.
+const FETCH_ATTEMPTS: usize = 3;
+
+/// Cached value with the generation it was fetched under.
+#[derive(Clone)]
+struct Cached<T> {
+    generation: u64,
+    value: T,
+}
+
+enum FetchError {
+    NotFound { generation: u64 },
+    Database(sqlx::Error),
+    TimedOut,
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::NotFound { .. } => write!(f, "Short URL not found"),
+            FetchError::Database(e) => write!(f, "Failed to fetch short URL: {}", e),
+            FetchError::TimedOut => write!(f, "Fetching short URL timed out after {:?}", FETCH_TIMEOUT),
+        }
+    }
+}
+
+/// Redirect lookups shared by all workers. Unknown codes are cached separately with a
+/// shorter TTL so scanners can't fill the cache meant for real links.
+///
+/// Every value remembers the generation it was fetched under and `invalidate` moves the code to
+/// a newer one, so a lookup that read the row before an update and finishes after it is never
+/// served.
+pub struct RedirectCache {
+    entries: Cache<String, Cached<Arc<ShortUrlModel>>>,
+    missing: Cache<String, Cached<()>>,
+    /// Generation each code was last invalidated at.
+    invalidated: Cache<String, u64>,
+    /// Generation of the last `invalidate_all`, or of the newest invalidation evicted from a full `invalidated`.
+    cleared: Arc<AtomicU64>,
+    generation: AtomicU64,
+    lookups: IntCounterVec,
+}
+
+impl RedirectCache {
+    pub fn new(application: &ApplicationSettings) -> Self {
+        let ttl = Duration::from_secs(application.redirect_cache_ttl_secs);
+        let negative_ttl = Duration::from_secs(application.redirect_cache_negative_ttl_secs);
+        let cleared = Arc::new(AtomicU64::new(0));
+        let evicted = cleared.clone();
+        Self {
+            entries: Cache::builder()
+                .max_capacity(application.redirect_cache_capacity)
+                .time_to_live(ttl)
+                .build(),
+            missing: Cache::builder()
+                .max_capacity(application.redirect_cache_capacity)
+                .time_to_live(negative_ttl)
+                .build(),
+            invalidated: Cache::builder()
+                .max_capacity(application.redirect_cache_capacity)
+                .time_to_live(ttl.max(negative_ttl) + FETCH_TIMEOUT)
+                .eviction_listener(move |_, generation, cause| {
+                    // Without its entry the code would look current again, so everything older is dropped instead.
+                    if cause == RemovalCause::Size {
+                        evicted.fetch_max(generation, Ordering::SeqCst);
+                    }
+                })
+                .build(),
+            cleared,
+            generation: AtomicU64::new(0),
+            lookups: IntCounterVec::new(
+                Opts::new("redirect_cache_lookups_total", "Redirect cache lookups by result: hit or miss"),
+                &["result"],
+            )
+            .expect("Valid metric options"),
+        }
+    }
+
+    #[tracing::instrument(name = "redirect_cache_get_or_fetch", skip(self, pool))]
+    pub async fn get_or_fetch(
+        &self,
+        pool: &PgPool,
+        short_url: &str,
+    ) -> Result<Option<Arc<ShortUrlModel>>, anyhow::Error> {
+        if let Some(url) = self.cached(short_url).await {
+            self.lookups.with_label_values(&["hit"]).inc();
+            return Ok(url);
+        }
+        self.lookups.with_label_values(&["miss"]).inc();
+        for _ in 0..FETCH_ATTEMPTS {
+            // Concurrent misses for the same code share one database lookup.
+            let entry = self
+                .entries
+                .entry_by_ref(short_url)
+                .or_try_insert_with(self.fetch(pool, short_url))
+                .await;
+            match entry {
+                Ok(entry) if self.is_current(short_url, entry.value().generation).await => {
+                    return Ok(Some(entry.into_value().value));
+                }
+                Ok(_) => self.entries.invalidate(short_url).await,
+                Err(e) => match e.as_ref() {
+                    FetchError::NotFound { generation } => {
+                        let missing = Cached { generation: *generation, value: () };
+                        self.missing.insert(short_url.to_string(), missing).await;
+                        if self.is_current(short_url, *generation).await {
+                            return Ok(None);
+                        }
+                        self.missing.invalidate(short_url).await;
+                    }
+                    _ => return Err(anyhow::anyhow!("{}", e)),
+                },
+            }
+        }
+        match self.fetch(pool, short_url).await {
+            Ok(url) => Ok(Some(url.value)),
+            Err(FetchError::NotFound { .. }) => Ok(None),
+            Err(e) => Err(anyhow::anyhow!("{}", e)),
+        }
+    }
+
+    /// `None` when the code has to be looked up, stale values are dropped on the way.
+    async fn cached(&self, short_url: &str) -> Option<Option<Arc<ShortUrlModel>>> {
+        if let Some(url) = self.entries.get(short_url).await {
+            if self.is_current(short_url, url.generation).await {
+                return Some(Some(url.value));
+            }
+            self.entries.invalidate(short_url).await;
+        }
+        if let Some(missing) = self.missing.get(short_url).await {
+            if self.is_current(short_url, missing.generation).await {
+                return Some(None);
+            }
+            self.missing.invalidate(short_url).await;
+        }
+        None
+    }
+
+    async fn fetch(&self, pool: &PgPool, short_url: &str) -> Result<Cached<Arc<ShortUrlModel>>, FetchError> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        match tokio::time::timeout(FETCH_TIMEOUT, fetch_short_url(pool, short_url)).await {
+            Ok(Ok(Some(url))) => Ok(Cached { generation, value: Arc::new(url) }),
+            Ok(Ok(None)) => Err(FetchError::NotFound { generation }),
+            Ok(Err(e)) => Err(FetchError::Database(e)),
+            Err(_) => Err(FetchError::TimedOut),
+        }
+    }
+
+    /// Whether a value fetched under `generation` is still valid.
+    async fn is_current(&self, short_url: &str, generation: u64) -> bool {
+        generation >= self.cleared.load(Ordering::SeqCst)
+            && self.invalidated.get(short_url).await.is_none_or(|invalidated| generation >= invalidated)
+    }
+
+    pub async fn invalidate(&self, short_url: &str) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.invalidated.insert(short_url.to_string(), generation).await;
+        self.entries.invalidate(short_url).await;
+        self.missing.invalidate(short_url).await;
+    }
+
+    /// Used when a change affects links we can't enumerate cheaply, e.g. a user being deactivated.
+    pub fn invalidate_all(&self) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        self.cleared.fetch_max(generation, Ordering::SeqCst);
+        self.entries.invalidate_all();
+        self.missing.invalidate_all();
+    }
+
+    /// Hits are lookups answered without the database, for known and unknown codes alike.
+    pub fn lookups(&self) -> &IntCounterVec {
+        &self.lookups
+    }
+}
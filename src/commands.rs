@@ -1,8 +1,28 @@
 
-use secrecy::ExposeSecret;
 use sqlx::PgPool;
 
-use crate::utils::{configure_database, generate_jwt_token_for_user, get_configuration, get_user_id};
+use crate::jwt_keys::JwtKeys;
+use crate::organizations::{fetch_organization_by_name, insert_organization, list_organizations, update_organization, validate_create_organization_request, validate_update_organization_request};
+use crate::quotas::{fetch_link_quota, set_link_quota, validate_set_link_quota_request, QuotaSubject};
+use crate::schemas::{CreateOrganizationRequest, SetLinkQuotaRequest, UpdateOrganizationRequest, UpdateUserRequest, UserRole};
+use crate::users::{delete_user, fetch_user_by_username, insert_user, list_users, update_user, validate_username};
+use crate::tokens::{issue_token_pair, revoke_user_tokens};
+use crate::utils::{configure_database, get_configuration, get_user_id};
+
+const USAGE: &str = "Usage:
+    migrate
+    generate_token <username>
+    create_organization <name>
+    list_organizations
+    update_organization <name> <name|default_expiry_days|allowed_domains|short_url_length> <value>
+    create_user <username> <organization> [read_only|member|admin]
+    list_users
+    update_user <username> <username|organization|role> <value>
+    deactivate_user <username>
+    activate_user <username>
+    delete_user <username>
+    revoke_tokens <username>
+    set_quota <user|organization> <name> <max_active_links|max_links_per_day|max_links_per_month> <value|none>";
 
 #[tracing::instrument(name = "Default Migration")]
 pub async fn run_migrations() {
@@ -18,16 +38,188 @@ pub async fn run_migrations() {
 pub async fn generate_user_token(username: &str) {
     let configuration = get_configuration().expect("Failed to read configuration.");
     let connection_pool = PgPool::connect_with(configuration.database.with_db()).await.expect("Failed to connect to Postgres.");
-    let token = match get_user_id(&connection_pool, username).await {
-        Ok(Some(id)) =>  
-            generate_jwt_token_for_user(id, configuration.secret.jwt.expiry, &configuration.secret.jwt.secret).map_err(|e| anyhow::anyhow!("JWT generation error: {}", e)),
+    let jwt_keys = JwtKeys::from_settings(&configuration.secret.jwt).expect("Failed to load JWT keys.");
+    let tokens = match fetch_user_by_username(&connection_pool, username).await {
+        Ok(Some(user)) => issue_token_pair(&connection_pool, &user.id, &configuration.secret.jwt, &jwt_keys)
+            .await
+            .and_then(|tokens| tokens.ok_or_else(|| anyhow::anyhow!("User is inactive"))),
         Ok(None) => Err(anyhow::anyhow!("User not found")),
         Err(e) => Err(anyhow::anyhow!("Database error: {}", e)),
     };
-    eprint!("Token for {} is: {}", username, token.unwrap().expose_secret())
+    let tokens = tokens.unwrap();
+    eprintln!("Refresh token for {} is: {}", username, tokens.refresh_token);
+    eprint!("Token for {} is: {}", username, tokens.access_token)
+}
+
+
+
+async fn connect_pool() -> Result<PgPool, anyhow::Error> {
+    let configuration = get_configuration()?;
+    Ok(PgPool::connect_with(configuration.database.with_db()).await?)
+}
+
+async fn get_existing_user_id(pool: &PgPool, username: &str) -> Result<uuid::Uuid, anyhow::Error> {
+    get_user_id(pool, username)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("User {} not found", username))
+}
+
+async fn get_existing_organization_id(pool: &PgPool, name: &str) -> Result<uuid::Uuid, anyhow::Error> {
+    fetch_organization_by_name(pool, name)
+        .await?
+        .map(|organization| organization.id)
+        .ok_or_else(|| anyhow::anyhow!("Organization {} not found", name))
+}
+
+#[tracing::instrument(name = "Create organization")]
+pub async fn create_organization_account(name: &str) -> Result<(), anyhow::Error> {
+    let mut req = CreateOrganizationRequest {
+        name: name.to_string(),
+        default_expiry_days: None,
+        allowed_domains: vec![],
+        short_url_length: None,
+    };
+    validate_create_organization_request(&mut req)?;
+    let pool = connect_pool().await?;
+    let organization = insert_organization(&pool, &req).await?;
+    eprintln!("Created organization {} with id {}", organization.name, organization.id);
+    Ok(())
+}
+
+#[tracing::instrument(name = "List organizations")]
+pub async fn list_organization_accounts() -> Result<(), anyhow::Error> {
+    let pool = connect_pool().await?;
+    for organization in list_organizations(&pool).await? {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            organization.id,
+            organization.name,
+            organization.default_expiry_days.map_or("-".to_string(), |days| format!("{}d", days)),
+            organization.short_url_length.map_or("-".to_string(), |length| length.to_string()),
+            organization.allowed_domains.join(",")
+        );
+    }
+    Ok(())
 }
 
+#[tracing::instrument(name = "Update organization")]
+pub async fn update_organization_account(name: &str, mut req: UpdateOrganizationRequest) -> Result<(), anyhow::Error> {
+    validate_update_organization_request(&mut req)?;
+    let pool = connect_pool().await?;
+    let id = get_existing_organization_id(&pool, name).await?;
+    let organization = update_organization(&pool, &id, &req)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Organization {} not found", name))?;
+    eprintln!(
+        "Updated organization {}: name={}, default_expiry_days={:?}, short_url_length={:?}, allowed_domains={:?}",
+        organization.id,
+        organization.name,
+        organization.default_expiry_days,
+        organization.short_url_length,
+        organization.allowed_domains
+    );
+    Ok(())
+}
+
+#[tracing::instrument(name = "Create user")]
+pub async fn create_user_account(username: &str, organization: &str, role: UserRole) -> Result<(), anyhow::Error> {
+    validate_username(username)?;
+    let pool = connect_pool().await?;
+    let organization_id = get_existing_organization_id(&pool, organization).await?;
+    let user = insert_user(&pool, username, &organization_id, role).await?;
+    eprintln!("Created {} user {} in {} with id {}", user.role, user.username, organization, user.id);
+    Ok(())
+}
 
+#[tracing::instrument(name = "List users")]
+pub async fn list_user_accounts() -> Result<(), anyhow::Error> {
+    let pool = connect_pool().await?;
+    let (users, _) = list_users(&pool, i64::MAX, 0).await?;
+    for user in users {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            user.id,
+            user.username,
+            user.organization_id,
+            user.role,
+            if user.is_active { "active" } else { "inactive" }
+        );
+    }
+    Ok(())
+}
+
+#[tracing::instrument(name = "Update user")]
+pub async fn update_user_account(username: &str, mut req: UpdateUserRequest, organization: Option<&str>) -> Result<(), anyhow::Error> {
+    if let Some(new_username) = &req.username {
+        validate_username(new_username)?;
+    }
+    let pool = connect_pool().await?;
+    if let Some(organization) = organization {
+        req.organization_id = Some(get_existing_organization_id(&pool, organization).await?);
+    }
+    let id = get_existing_user_id(&pool, username).await?;
+    let user = update_user(&pool, &id, &req)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("User {} not found", username))?;
+    eprintln!(
+        "Updated user {}: username={}, organization_id={}, role={}, active={}",
+        user.id, user.username, user.organization_id, user.role, user.is_active
+    );
+    Ok(())
+}
+
+#[tracing::instrument(name = "Delete user")]
+pub async fn delete_user_account(username: &str) -> Result<(), anyhow::Error> {
+    let pool = connect_pool().await?;
+    let id = get_existing_user_id(&pool, username).await?;
+    delete_user(&pool, &id).await?;
+    eprintln!("Deleted user {}", username);
+    Ok(())
+}
+
+#[tracing::instrument(name = "Revoke user tokens")]
+pub async fn revoke_user_account_tokens(username: &str) -> Result<(), anyhow::Error> {
+    let pool = connect_pool().await?;
+    let id = get_existing_user_id(&pool, username).await?;
+    revoke_user_tokens(&pool, &id).await?;
+    eprintln!("Revoked all tokens of user {}", username);
+    Ok(())
+}
+
+#[tracing::instrument(name = "Set link quota")]
+pub async fn set_account_link_quota(kind: &str, name: &str, field: &str, value: &str) -> Result<(), anyhow::Error> {
+    let limit = match value {
+        "none" => None,
+        value => Some(value.parse()?),
+    };
+    let pool = connect_pool().await?;
+    let subject = match kind {
+        "user" => QuotaSubject::User(get_existing_user_id(&pool, name).await?),
+        "organization" => QuotaSubject::Organization(get_existing_organization_id(&pool, name).await?),
+        kind => return Err(anyhow::anyhow!("Unknown quota subject {}", kind)),
+    };
+    let quota = fetch_link_quota(&pool, subject).await?;
+    let mut req = SetLinkQuotaRequest {
+        max_active_links: quota.max_active_links,
+        max_links_per_day: quota.max_links_per_day,
+        max_links_per_month: quota.max_links_per_month,
+    };
+    match field {
+        "max_active_links" => req.max_active_links = limit,
+        "max_links_per_day" => req.max_links_per_day = limit,
+        "max_links_per_month" => req.max_links_per_month = limit,
+        field => return Err(anyhow::anyhow!("Unknown quota field {}", field)),
+    }
+    validate_set_link_quota_request(&req)?;
+    let quota = set_link_quota(&pool, subject, &req)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("{} {} not found", kind, name))?;
+    eprintln!(
+        "Updated quota of {} {}: max_active_links={:?}, max_links_per_day={:?}, max_links_per_month={:?}",
+        kind, name, quota.max_active_links, quota.max_links_per_day, quota.max_links_per_month
+    );
+    Ok(())
+}
 
 #[tracing::instrument(name = "Run custom command")]
 pub async fn run_custom_commands(args: Vec<String>) -> Result<(), anyhow::Error> {
@@ -36,6 +228,56 @@ pub async fn run_custom_commands(args: Vec<String>) -> Result<(), anyhow::Error>
             run_migrations().await;
         } else if args[1] == "generate_token" && args.len() > 2 {
             generate_user_token(&args[2]).await;
+        } else if args[1] == "create_organization" && args.len() > 2 {
+            create_organization_account(&args[2]).await?;
+        } else if args[1] == "list_organizations" {
+            list_organization_accounts().await?;
+        } else if args[1] == "update_organization" && args.len() > 4 {
+            let mut req = UpdateOrganizationRequest { name: None, default_expiry_days: None, allowed_domains: None, short_url_length: None };
+            match args[3].as_str() {
+                "name" => req.name = Some(args[4].clone()),
+                "default_expiry_days" => req.default_expiry_days = Some(args[4].parse()?),
+                "allowed_domains" => {
+                    req.allowed_domains = Some(args[4].split(',').filter(|d| !d.is_empty()).map(str::to_string).collect())
+                }
+                "short_url_length" => req.short_url_length = Some(args[4].parse()?),
+                field => return Err(anyhow::anyhow!("Unknown organization field {}", field)),
+            }
+            update_organization_account(&args[2], req).await?;
+        } else if args[1] == "create_user" && args.len() > 3 {
+            let role = match args.get(4) {
+                Some(role) => role.parse()?,
+                None => UserRole::default(),
+            };
+            create_user_account(&args[2], &args[3], role).await?;
+        } else if args[1] == "list_users" {
+            list_user_accounts().await?;
+        } else if args[1] == "update_user" && args.len() > 4 {
+            let mut req = UpdateUserRequest { username: None, organization_id: None, is_active: None, role: None };
+            let mut organization = None;
+            match args[3].as_str() {
+                "username" => req.username = Some(args[4].clone()),
+                "organization" => organization = Some(args[4].as_str()),
+                "role" => req.role = Some(args[4].parse()?),
+                field => return Err(anyhow::anyhow!("Unknown user field {}", field)),
+            }
+            update_user_account(&args[2], req, organization).await?;
+        } else if (args[1] == "deactivate_user" || args[1] == "activate_user") && args.len() > 2 {
+            let req = UpdateUserRequest {
+                username: None,
+                organization_id: None,
+                is_active: Some(args[1] == "activate_user"),
+                role: None,
+            };
+            update_user_account(&args[2], req, None).await?;
+        } else if args[1] == "delete_user" && args.len() > 2 {
+            delete_user_account(&args[2]).await?;
+        } else if args[1] == "revoke_tokens" && args.len() > 2 {
+            revoke_user_account_tokens(&args[2]).await?;
+        } else if args[1] == "set_quota" && args.len() > 5 {
+            set_account_link_quota(&args[2], &args[3], &args[4], &args[5]).await?;
+        } else {
+            eprintln!("Invalid command.\n{}", USAGE);
         }
     } else {
         eprintln!("Invalid command. Please enter a valid command.");
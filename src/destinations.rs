@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use url::Url;
+
+use crate::domain_rules::{domain_matches, normalize_domain_pattern};
+use crate::errors::GenericError;
+use crate::schemas::{ApplicationSettings, ShortenerPolicy};
+use crate::utils::{fetch_short_url, normalize_url};
+
+/// Detects destinations that are themselves short links, on our own domain or on a known
+/// shortener, and either rejects them or follows the chain to the final target.
+pub struct DestinationResolver {
+    own_domain: String,
+    shortener_domains: Vec<String>,
+    policy: ShortenerPolicy,
+    max_depth: usize,
+    allowed_schemes: Vec<String>,
+    client: reqwest::Client,
+}
+
+impl DestinationResolver {
+    pub fn new(application: &ApplicationSettings) -> Result<Self, anyhow::Error> {
+        let host = application.domain.split(':').next().unwrap_or_default();
+        let own_domain = normalize_domain_pattern(host)
+            .map_err(|e| anyhow::anyhow!("Invalid application domain '{}': {}", application.domain, e))?;
+        let shortener_domains = application
+            .shortener_domains
+            .iter()
+            .map(|domain| {
+                normalize_domain_pattern(domain)
+                    .map_err(|e| anyhow::anyhow!("Invalid shortener domain '{}': {}", domain, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(Duration::from_millis(application.shortener_resolve_timeout_ms))
+            .build()?;
+        Ok(Self {
+            own_domain,
+            shortener_domains,
+            policy: application.shortener_policy,
+            max_depth: application.shortener_max_depth,
+            allowed_schemes: application.allowed_url_schemes.clone(),
+            client,
+        })
+    }
+
+    /// `Some(true)` for links on our own domain, `Some(false)` for other shorteners.
+    fn shortener_kind(&self, url: &Url) -> Option<bool> {
+        let host = url.host_str()?.trim_end_matches('.');
+        if domain_matches(&self.own_domain, host) {
+            Some(true)
+        } else if self.shortener_domains.iter().any(|pattern| domain_matches(pattern, host)) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Expects a URL that already went through `normalize_url` and returns the destination to store.
+    #[tracing::instrument(name = "resolve_destination", skip(self, pool))]
+    pub async fn resolve(&self, pool: &PgPool, original_url: &str) -> Result<String, GenericError> {
+        let mut current = original_url.to_string();
+        let mut depth = 0;
+        loop {
+            let url = Url::parse(&current)
+                .map_err(|e| GenericError::ValidationError(format!("Invalid URL: {}", e)))?;
+            let Some(own) = self.shortener_kind(&url) else {
+                return Ok(current);
+            };
+            if self.policy == ShortenerPolicy::Reject {
+                return Err(GenericError::ValidationError(
+                    "Destination must not be another short link".to_string(),
+                ));
+            }
+            if depth == self.max_depth {
+                return Err(GenericError::ValidationError(format!(
+                    "Destination redirects through more than {} short links",
+                    self.max_depth
+                )));
+            }
+            let next = if own {
+                self.lookup_own_link(pool, &url).await?
+            } else {
+                self.follow_redirect(&url).await?
+            };
+            current = normalize_url(&next, &self.allowed_schemes)?;
+            depth += 1;
+        }
+    }
+
+    async fn lookup_own_link(&self, pool: &PgPool, url: &Url) -> Result<String, GenericError> {
+        let code = url
+            .path_segments()
+            .and_then(|mut segments| segments.next())
+            .unwrap_or_default();
+        match fetch_short_url(pool, code).await {
+            Ok(Some(link)) if !link.is_expired() => Ok(link.original_url),
+            Ok(_) => Err(GenericError::ValidationError(
+                "Destination is a short link that does not exist".to_string(),
+            )),
+            Err(e) => Err(GenericError::UnexpectedError(e.into())),
+        }
+    }
+
+    async fn follow_redirect(&self, url: &Url) -> Result<String, GenericError> {
+        let unresolvable = || {
+            GenericError::ValidationError(format!("Could not resolve short link '{}'", url))
+        };
+        let response = self.client.get(url.clone()).send().await.map_err(|e| {
+            tracing::warn!("Failed to resolve {}: {}", url, e);
+            unresolvable()
+        })?;
+        if !response.status().is_redirection() {
+            return Err(unresolvable());
+        }
+        response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| url.join(location).ok())
+            .map(String::from)
+            .ok_or_else(unresolvable)
+    }
+}
@@ -0,0 +1,173 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::PgPool;
+use tokio::task::JoinHandle;
+use url::{Host, Url};
+use uuid::Uuid;
+
+use crate::errors::GenericError;
+use crate::models::DomainRuleModel;
+use crate::schemas::DomainRuleType;
+
+/// Canonical form of a rule pattern: lowercase, punycode labels, no trailing dot. `*` is only
+/// accepted as a whole label so that every other label can go through IDNA like a real host.
+pub fn normalize_domain_pattern(pattern: &str) -> Result<String, GenericError> {
+    let pattern = pattern.trim().trim_end_matches('.').to_lowercase();
+    if pattern.is_empty() {
+        return Err(GenericError::ValidationError("Domain pattern must not be empty".to_string()));
+    }
+    pattern
+        .split('.')
+        .map(|label| match label {
+            "*" => Ok(label.to_string()),
+            _ if label.contains('*') => Err(GenericError::ValidationError(
+                "Wildcard '*' must be a whole label, e.g. '*.example.com'".to_string(),
+            )),
+            _ => match Host::parse(label) {
+                Ok(Host::Domain(label)) if !label.is_empty() => Ok(label),
+                _ => Err(GenericError::ValidationError(format!(
+                    "Invalid domain label '{}'",
+                    label
+                ))),
+            },
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(|labels| labels.join("."))
+}
+
+/// A pattern without wildcards matches the domain and all of its subdomains. With wildcards,
+/// every `*` matches exactly one label, except a leading `*` which matches one or more.
+pub fn domain_matches(pattern: &str, host: &str) -> bool {
+    if !pattern.contains('*') {
+        return host == pattern || host.ends_with(&format!(".{}", pattern));
+    }
+    let pattern_labels: Vec<&str> = pattern.split('.').collect();
+    let host_labels: Vec<&str> = host.split('.').collect();
+    let (leading_wildcard, pattern_labels) = match pattern_labels.split_first() {
+        Some((&"*", rest)) => (true, rest),
+        _ => (false, &pattern_labels[..]),
+    };
+    let matches_tail = |tail: &[&str]| {
+        tail.iter()
+            .zip(pattern_labels)
+            .all(|(label, pattern)| *pattern == "*" || label == pattern)
+    };
+    if leading_wildcard {
+        host_labels.len() > pattern_labels.len()
+            && matches_tail(&host_labels[host_labels.len() - pattern_labels.len()..])
+    } else {
+        host_labels.len() == pattern_labels.len() && matches_tail(&host_labels)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DomainRuleSet {
+    blocked: Vec<String>,
+    allowed: Vec<String>,
+}
+
+impl DomainRuleSet {
+    pub fn new(rules: Vec<DomainRuleModel>) -> Self {
+        let mut set = Self::default();
+        for rule in rules {
+            match rule.rule_type {
+                DomainRuleType::Block => set.blocked.push(rule.pattern),
+                DomainRuleType::Allow => set.allowed.push(rule.pattern),
+            }
+        }
+        set
+    }
+
+    /// A host is permitted when no block rule matches and, if an allowlist exists, an allow rule does.
+    pub fn is_permitted(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        if self.blocked.iter().any(|pattern| domain_matches(pattern, host)) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.iter().any(|pattern| domain_matches(pattern, host))
+    }
+
+    pub fn is_url_permitted(&self, url: &str) -> bool {
+        match Url::parse(url).ok().as_ref().and_then(Url::host_str) {
+            Some(host) => self.is_permitted(host),
+            None => false,
+        }
+    }
+}
+
+/// In-memory snapshot of the `domain_rule` table, reloaded after every admin change and
+/// periodically so that other instances pick up changes too.
+#[derive(Default)]
+pub struct DomainPolicy {
+    rules: RwLock<Arc<DomainRuleSet>>,
+}
+
+impl DomainPolicy {
+    pub fn current(&self) -> Arc<DomainRuleSet> {
+        self.rules.read().expect("domain rules lock poisoned").clone()
+    }
+
+    #[tracing::instrument(name = "reload_domain_policy", skip_all)]
+    pub async fn reload(&self, pool: &PgPool) -> Result<(), anyhow::Error> {
+        let rules = fetch_domain_rules(pool).await?;
+        *self.rules.write().expect("domain rules lock poisoned") = Arc::new(DomainRuleSet::new(rules));
+        Ok(())
+    }
+
+    pub fn spawn_refresh(self: Arc<Self>, pool: PgPool, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.reload(&pool).await {
+                    tracing::error!("Failed to reload domain rules: {:?}", e);
+                }
+            }
+        })
+    }
+}
+
+#[tracing::instrument(name = "fetch_domain_rules", skip(pool))]
+pub async fn fetch_domain_rules(pool: &PgPool) -> Result<Vec<DomainRuleModel>, anyhow::Error> {
+    let rules = sqlx::query_as!(
+        DomainRuleModel,
+        r#"SELECT id, pattern, rule_type AS "rule_type: DomainRuleType", created_on, created_by
+        FROM domain_rule ORDER BY id"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rules)
+}
+
+#[tracing::instrument(name = "insert_domain_rule", skip(pool))]
+pub async fn insert_domain_rule(
+    pool: &PgPool,
+    pattern: &str,
+    rule_type: DomainRuleType,
+    created_by: &Uuid,
+) -> Result<Option<DomainRuleModel>, anyhow::Error> {
+    let rule = sqlx::query_as!(
+        DomainRuleModel,
+        r#"INSERT INTO domain_rule (pattern, rule_type, created_on, created_by)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (pattern, rule_type) DO NOTHING
+        RETURNING id, pattern, rule_type AS "rule_type: DomainRuleType", created_on, created_by"#,
+        pattern,
+        rule_type as DomainRuleType,
+        Utc::now(),
+        created_by
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(rule)
+}
+
+#[tracing::instrument(name = "delete_domain_rule", skip(pool))]
+pub async fn delete_domain_rule(pool: &PgPool, id: i32) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!("DELETE FROM domain_rule WHERE id = $1", id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -17,6 +17,20 @@ impl std::fmt::Debug for CustomJWTTokenError {
     }
 }
 
+#[derive(thiserror::Error)]
+pub enum ShortUrlInsertError {
+    #[error("Short URL already exists")]
+    AlreadyExists,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ShortUrlInsertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
 #[derive(thiserror::Error)]
 pub enum GenericError {
     #[error("{0}")]
@@ -25,6 +39,16 @@ pub enum GenericError {
     UnexpectedError(#[from] anyhow::Error),
     #[error("{0}")]
     InvalidJWT(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    TooManyRequests(String),
+    #[error("{0}")]
+    QuotaExceeded(String),
 
 }
 
@@ -34,6 +58,16 @@ impl std::fmt::Debug for GenericError {
     }
 }
 
+impl GenericError {
+    /// The `code` of error responses. The status code, unless clients need to tell errors with the same status apart.
+    pub fn code(&self) -> String {
+        match self {
+            GenericError::QuotaExceeded(_) => "quota_exceeded".to_string(),
+            _ => self.status_code().as_str().to_string(),
+        }
+    }
+}
+
 impl ResponseError for GenericError {
     fn status_code(&self) -> StatusCode {
         match self {
@@ -41,21 +75,30 @@ impl ResponseError for GenericError {
             GenericError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
 
             GenericError::InvalidJWT(_) => StatusCode::UNAUTHORIZED,
+            GenericError::Conflict(_) => StatusCode::CONFLICT,
+            GenericError::NotFound(_) => StatusCode::NOT_FOUND,
+            GenericError::Forbidden(_) => StatusCode::FORBIDDEN,
+            GenericError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            GenericError::QuotaExceeded(_) => StatusCode::FORBIDDEN,
         }
     }
 
     fn error_response(&self) -> HttpResponse {
         let status_code = self.status_code();
-        let status_code_str = status_code.as_str();
         let inner_error_msg = match self {
             GenericError::ValidationError(message) => message.to_string(),
             GenericError::UnexpectedError(error_msg) => error_msg.to_string(),
             GenericError::InvalidJWT(error_msg) => error_msg.to_string(),
+            GenericError::Conflict(error_msg) => error_msg.to_string(),
+            GenericError::NotFound(error_msg) => error_msg.to_string(),
+            GenericError::Forbidden(error_msg) => error_msg.to_string(),
+            GenericError::TooManyRequests(error_msg) => error_msg.to_string(),
+            GenericError::QuotaExceeded(error_msg) => error_msg.to_string(),
         };
 
         HttpResponse::build(status_code).json(GenericResponse::error(
             &inner_error_msg,
-            status_code_str,
+            &self.code(),
             Some(()),
         ))
     }
@@ -0,0 +1,109 @@
+use rand::seq::SliceRandom;
+use sqids::Sqids;
+
+use crate::errors::GenericError;
+use crate::schemas::{ApplicationSettings, ShortUrlStrategy};
+use crate::utils::generate_short_url;
+
+/// Alphabet without the look-alike characters `0`, `O`, `1`, `l` and `I`.
+const HUMAN_FRIENDLY_ALPHABET: &[u8] = b"23456789abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ";
+
+pub trait ShortUrlGenerator: Send + Sync {
+    /// Sequential generators derive the code from the `short_url.id` the row is stored under.
+    fn is_sequential(&self) -> bool {
+        false
+    }
+
+    /// `id` is only meaningful for sequential generators, the others ignore it.
+    fn generate(&self, id: i32, length: usize) -> Result<String, anyhow::Error>;
+}
+
+pub struct RandomGenerator;
+
+impl ShortUrlGenerator for RandomGenerator {
+    fn generate(&self, _id: i32, length: usize) -> Result<String, anyhow::Error> {
+        Ok(generate_short_url(length))
+    }
+}
+
+pub struct HumanFriendlyGenerator;
+
+impl ShortUrlGenerator for HumanFriendlyGenerator {
+    fn generate(&self, _id: i32, length: usize) -> Result<String, anyhow::Error> {
+        let mut rng = rand::thread_rng();
+        Ok((0..length)
+            .map(|_| *HUMAN_FRIENDLY_ALPHABET.choose(&mut rng).unwrap() as char)
+            .collect())
+    }
+}
+
+pub struct SequentialGenerator {
+    sqids: Sqids,
+}
+
+impl SequentialGenerator {
+    pub fn new(alphabet: &str, min_length: usize) -> Result<Self, anyhow::Error> {
+        let sqids = Sqids::builder()
+            .alphabet(alphabet.chars().collect())
+            .min_length(min_length.min(u8::MAX as usize) as u8)
+            .build()
+            .map_err(|e| anyhow::anyhow!("Invalid sequential alphabet: {}", e))?;
+        Ok(Self { sqids })
+    }
+}
+
+impl ShortUrlGenerator for SequentialGenerator {
+    fn is_sequential(&self) -> bool {
+        true
+    }
+
+    fn generate(&self, id: i32, _length: usize) -> Result<String, anyhow::Error> {
+        self.sqids
+            .encode(&[id as u64])
+            .map_err(|e| anyhow::anyhow!("Failed to encode short URL id {}: {}", id, e))
+    }
+}
+
+pub struct ShortUrlGenerators {
+    random: RandomGenerator,
+    /// `None` without `APPLICATION__SEQUENTIAL_ALPHABET`.
+    sequential: Option<SequentialGenerator>,
+    human_friendly: HumanFriendlyGenerator,
+}
+
+impl ShortUrlGenerators {
+    pub fn new(application: &ApplicationSettings) -> Result<Self, anyhow::Error> {
+        let sequential = match application.sequential_alphabet.as_deref() {
+            Some(alphabet) if alphabet == sqids::DEFAULT_ALPHABET => {
+                return Err(anyhow::anyhow!(
+                    "APPLICATION__SEQUENTIAL_ALPHABET must be shuffled, codes made with the default sqids alphabet decode to row ids"
+                ));
+            }
+            Some(alphabet) => Some(SequentialGenerator::new(alphabet, application.short_url_length)?),
+            None => None,
+        };
+        if sequential.is_none() && application.short_url_strategy == ShortUrlStrategy::Sequential {
+            return Err(anyhow::anyhow!(
+                "APPLICATION__SHORT_URL_STRATEGY=sequential requires APPLICATION__SEQUENTIAL_ALPHABET"
+            ));
+        }
+        Ok(Self {
+            random: RandomGenerator,
+            sequential,
+            human_friendly: HumanFriendlyGenerator,
+        })
+    }
+
+    pub fn get(&self, strategy: &ShortUrlStrategy) -> Result<&dyn ShortUrlGenerator, GenericError> {
+        match strategy {
+            ShortUrlStrategy::Random => Ok(&self.random),
+            ShortUrlStrategy::Sequential => match &self.sequential {
+                Some(sequential) => Ok(sequential),
+                None => Err(GenericError::ValidationError(
+                    "The sequential strategy is not enabled on this server".to_string(),
+                )),
+            },
+            ShortUrlStrategy::HumanFriendly => Ok(&self.human_friendly),
+        }
+    }
+}
@@ -1,9 +1,52 @@
-use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
-use crate::{errors::GenericError, schemas::ApplicationSettings, utils::{generate_short_url, get_original_url, insert_url}};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use crate::{analytics::{fetch_click_breakdowns, fetch_click_totals, fetch_clicks_over_time, fetch_top_links, ClickEvent, ClickRecorder}, api_keys::{generate_api_key, hash_api_key, insert_api_key, list_user_api_keys, revoke_user_api_key}, cache::RedirectCache, destinations::DestinationResolver, domain_rules::{delete_domain_rule, fetch_domain_rules, insert_domain_rule, normalize_domain_pattern, DomainPolicy}, errors::{GenericError, ShortUrlInsertError}, generators::ShortUrlGenerators, health::check_readiness, jwt_keys::JwtKeys, metrics::{Metrics, RedirectOutcome}, models::OrganizationModel, quotas::{check_link_quota, fetch_link_quota, fetch_link_usage, lock_link_usage, set_link_quota, validate_set_link_quota_request, QuotaSubject}, organizations::{check_organization_domain, delete_organization, fetch_organization, insert_organization, list_organizations, organization_code_length, update_organization, validate_create_organization_request, validate_update_organization_request}, schemas::{ApplicationSettings, AuthenticatedUser, SecretSetting}, tokens::{revoke_refresh_token, revoke_user_tokens, rotate_refresh_token}, users::{count_users, delete_user, fetch_user, insert_user, list_users, update_user, validate_username}, utils::{check_destination_domain, count_short_urls, delete_org_short_url, fetch_org_short_url, get_request_user, get_request_user_id, insert_bulk_urls, insert_generated_url, insert_url, list_org_short_urls, normalize_url, update_org_short_url, validate_create_url_request}};
+use chrono::{DateTime, Duration, Utc};
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::schemas::{CreateUrlRequest, CreateUrlResponseData, GenericResponse};
+use crate::schemas::{ApiKeyData, BulkCreateUrlRequest, BulkCreateUrlResponseData, BulkCreateUrlResult, CreateApiKeyRequest, CreateApiKeyResponseData, CreateDomainRuleRequest, CreateOrganizationRequest, CreateUrlRequest, CreateUrlResponseData, CreateUserRequest, DomainRuleData, GenericResponse, GlobalStatsData, LinkQuotaData, LinkUsageData, ListUrlQuery, ListUserQuery, OrganizationData, ReadinessData, RefreshTokenRequest, SetLinkQuotaRequest, StatsInterval, TokenPairData, UpdateOrganizationRequest, UpdateUrlRequest, UpdateUserRequest, UrlData, UrlListData, UrlStatsData, UrlStatsQuery, UserData, UserListData};
+
+const DEFAULT_PAGE_LIMIT: i64 = 20;
+const MAX_PAGE_LIMIT: i64 = 100;
+const DEFAULT_STATS_DAYS: i64 = 30;
+const STATS_TOP_LIMIT: i64 = 10;
+const BLOCKED_REDIRECT_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head><meta charset="utf-8"><title>Link disabled</title></head>
+<body>
+<h1>This link has been disabled</h1>
+<p>The destination of this short link is on a domain that is blocked by our link policy, so you have not been redirected.</p>
+</body>
+</html>"#;
+
+/// Page and limit with their defaults applied, and the offset of the page.
+fn page_offset(page: Option<i64>, limit: Option<i64>) -> Result<(i64, i64, i64), GenericError> {
+    let page = page.unwrap_or(1);
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+    if page < 1 {
+        return Err(GenericError::ValidationError("Page must be at least 1".to_string()));
+    }
+    if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+        return Err(GenericError::ValidationError(format!(
+            "Limit must be between 1 and {}",
+            MAX_PAGE_LIMIT
+        )));
+    }
+    (page - 1)
+        .checked_mul(limit)
+        .map(|offset| (page, limit, offset))
+        .ok_or_else(|| GenericError::ValidationError("Page is too large".to_string()))
+}
+
+/// Range and interval of a stats query, the last `DEFAULT_STATS_DAYS` by day unless it says otherwise.
+fn stats_range(query: UrlStatsQuery) -> Result<(DateTime<Utc>, DateTime<Utc>, StatsInterval), GenericError> {
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - Duration::days(DEFAULT_STATS_DAYS));
+    if from >= to {
+        return Err(GenericError::ValidationError("'from' must be before 'to'".to_string()));
+    }
+    Ok((from, to, query.interval.unwrap_or_default()))
+}
 
 
 
@@ -14,32 +57,82 @@ use crate::schemas::{CreateUrlRequest, CreateUrlResponseData, GenericResponse};
     request_body(content = CreateUrlRequest, description = "Request Body"),
     responses(
         (status=200, description= "Create short URL", body= GenericResponse<CreateUrlResponseData>),
+        (status=403, description= "Link quota exceeded"),
+        (status=409, description= "Alias is already taken"),
     ),
     params(
         ("Authorization" = String, Header, description = "JWT token"),
     )
 )]
-#[tracing::instrument(name = "create_short_url", skip(pool))]
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "create_short_url", skip(pool, generators, redirect_cache, domain_policy, resolver, metrics))]
 pub async fn create_short_url(
     pool: web::Data<PgPool>,
-    req: CreateUrlRequest,
+    mut req: CreateUrlRequest,
     request: HttpRequest, 
     application: web::Data<ApplicationSettings>,
+    generators: web::Data<ShortUrlGenerators>,
+    redirect_cache: web::Data<RedirectCache>,
+    domain_policy: web::Data<DomainPolicy>,
+    resolver: web::Data<DestinationResolver>,
+    metrics: web::Data<Metrics>,
 ) -> Result<web::Json<GenericResponse<CreateUrlResponseData>>, GenericError>{
-    let user_id = match request.extensions().get::<Uuid>() {
-        Some(uuid) => *uuid, 
-        None => return Err(GenericError::ValidationError("User ID not found".to_string())),
+    let user = get_request_user(&request)?;
+    let organization = fetch_member_organization(&pool, &user).await?;
+    if req.alias.is_none() {
+        generators.get(req.strategy.as_ref().unwrap_or(&application.short_url_strategy))?;
+    }
+    validate_create_url_request(&pool, &mut req, &application, &domain_policy.current(), &resolver, &organization).await?;
+    // The quota stays locked until the link is committed.
+    let mut transaction = pool.begin().await.map_err(|e| GenericError::UnexpectedError(e.into()))?;
+    check_link_quota(&lock_link_usage(&mut transaction, &user).await?, 1)?;
+
+    let result = match &req.alias {
+        Some(alias) => {
+            insert_url(&mut *transaction, &req.original_url, alias, &user.id, Some(&organization.id), req.expiry_date, req.redirect_type)
+                .await
+                .map(|_| alias.to_owned())
+        }
+        None => {
+            let strategy = req.strategy.as_ref().unwrap_or(&application.short_url_strategy);
+            insert_generated_url(
+                &mut transaction,
+                generators.get(strategy)?,
+                &req.original_url,
+                &user.id,
+                Some(&organization.id),
+                req.expiry_date,
+                req.redirect_type,
+                organization_code_length(&organization, application.short_url_length),
+                application.short_url_retries,
+            )
+            .await
+        }
     };
-    
-    let short_url = generate_short_url();
-    match insert_url(&pool, &req.original_url, &short_url, &user_id).await {
-        Ok(_) => Ok(web::Json(GenericResponse::success(
-            "Successfully created short url",
-            Some(CreateUrlResponseData {
-                short_url: format!("https://{}/{}", &application.domain, &short_url),
-            }),
-        ))),
-        Err(_) =>  Err(GenericError::ValidationError("Internal Server Error".to_string())),
+    match result {
+        Ok(short_url) => {
+            transaction.commit().await.map_err(|e| GenericError::UnexpectedError(e.into()))?;
+            metrics.record_links_created("single", 1);
+            redirect_cache.invalidate(&short_url).await;
+            Ok(web::Json(GenericResponse::success(
+                "Successfully created short url",
+                Some(CreateUrlResponseData {
+                    short_url: format!("https://{}/{}", &application.domain, &short_url),
+                    expiry_date: req.expiry_date,
+                    redirect_type: req.redirect_type.unwrap_or(application.default_redirect_type),
+                }),
+            )))
+        }
+        Err(ShortUrlInsertError::AlreadyExists) => match &req.alias {
+            Some(alias) => Err(GenericError::Conflict(format!(
+                "Alias '{}' is already taken",
+                alias
+            ))),
+            None => Err(GenericError::UnexpectedError(anyhow::anyhow!(
+                "Could not generate a unique short URL"
+            ))),
+        },
+        Err(ShortUrlInsertError::UnexpectedError(e)) => Err(GenericError::UnexpectedError(e)),
     }
 }
 
@@ -47,24 +140,1513 @@ pub async fn create_short_url(
 
 #[utoipa::path(
     post,
+    path = "/shorten/bulk",
+    tag = "Create short URL",
+    request_body(content = BulkCreateUrlRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Create short URLs in bulk", body= GenericResponse<BulkCreateUrlResponseData>),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "create_bulk_short_urls", skip(pool, generators, redirect_cache, domain_policy, resolver, metrics, req), fields(count = req.urls.len(), mode = ?req.mode))]
+pub async fn create_bulk_short_urls(
+    pool: web::Data<PgPool>,
+    mut req: BulkCreateUrlRequest,
+    request: HttpRequest,
+    application: web::Data<ApplicationSettings>,
+    generators: web::Data<ShortUrlGenerators>,
+    redirect_cache: web::Data<RedirectCache>,
+    domain_policy: web::Data<DomainPolicy>,
+    resolver: web::Data<DestinationResolver>,
+    metrics: web::Data<Metrics>,
+) -> Result<web::Json<GenericResponse<BulkCreateUrlResponseData>>, GenericError> {
+    let user = get_request_user(&request)?;
+    if req.urls.is_empty() || req.urls.len() > application.bulk_create_limit {
+        return Err(GenericError::ValidationError(format!(
+            "Number of urls must be between 1 and {}",
+            application.bulk_create_limit
+        )));
+    }
+
+    let organization = fetch_member_organization(&pool, &user).await?;
+    let results = insert_bulk_urls(&pool, &generators, &application, &domain_policy.current(), &resolver, &user.id, &organization, Some(&user), &mut req).await?;
+    for short_url in results.iter().flatten() {
+        redirect_cache.invalidate(short_url).await;
+    }
+    let results: Vec<BulkCreateUrlResult> = results
+        .into_iter()
+        .zip(&req.urls)
+        .enumerate()
+        .map(|(index, (result, url))| match result {
+            Ok(short_url) => BulkCreateUrlResult {
+                index,
+                status: true,
+                code: "200".to_string(),
+                short_url: Some(format!("https://{}/{}", &application.domain, &short_url)),
+                expiry_date: url.expiry_date,
+                error: None,
+            },
+            Err(e) => BulkCreateUrlResult {
+                index,
+                status: false,
+                code: e.code(),
+                short_url: None,
+                expiry_date: url.expiry_date,
+                error: Some(e.to_string()),
+            },
+        })
+        .collect();
+    let created = results.iter().filter(|result| result.status).count();
+    metrics.record_links_created("bulk", created as u64);
+    Ok(web::Json(GenericResponse::success(
+        "Successfully processed bulk short url request",
+        Some(BulkCreateUrlResponseData {
+            created,
+            failed: results.len() - created,
+            results,
+        }),
+    )))
+}
+
+
+
+#[utoipa::path(
+    get,
     path = "/{short_url}",
     tag = "Redirect short URL",
     responses(
-        (status=200, description= "Redirect short URL"),
+        (status=301, description= "Redirect short URL permanently"),
+        (status=302, description= "Redirect short URL"),
+        (status=307, description= "Redirect short URL temporarily, preserving the method"),
+        (status=308, description= "Redirect short URL permanently, preserving the method"),
+        (status=403, description= "Destination domain is blocked, a warning page is returned"),
+        (status=404, description= "Short URL not found"),
+        (status=410, description= "Short URL has expired"),
     )
 )]
-#[tracing::instrument(name = "redirect_short_url", skip(pool))]
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "redirect_short_url", skip(pool, request, click_recorder, secret, redirect_cache, application, domain_policy, metrics))]
 pub async fn redirect_short_url(
     pool: web::Data<PgPool>,
     short_url: web::Path<String>,
+    request: HttpRequest,
+    click_recorder: web::Data<ClickRecorder>,
+    secret: web::Data<SecretSetting>,
+    redirect_cache: web::Data<RedirectCache>,
+    application: web::Data<ApplicationSettings>,
+    domain_policy: web::Data<DomainPolicy>,
+    metrics: web::Data<Metrics>,
 ) -> impl Responder {
-    match get_original_url(&pool, &short_url).await {
-        Ok(Some(original_url)) => HttpResponse::Found()
-            .append_header(("Location", original_url))
-            .finish(),
-        Ok(None) => HttpResponse::NotFound().finish(),
-        Err(_) => HttpResponse::InternalServerError().finish(),
+    match redirect_cache.get_or_fetch(&pool, &short_url).await {
+        Ok(Some(url)) if url.is_expired() => {
+            metrics.record_redirect(RedirectOutcome::Expired);
+            redirect_cache.invalidate(&short_url).await;
+            HttpResponse::Gone().finish()
+        }
+        Ok(Some(url)) if !domain_policy.current().is_url_permitted(&url.original_url) => {
+            metrics.record_redirect(RedirectOutcome::Blocked);
+            tracing::warn!("Refusing redirect of {} to a blocked destination", url.short_url);
+            HttpResponse::Forbidden()
+                .content_type("text/html; charset=utf-8")
+                .append_header(("Cache-Control", "no-store"))
+                .body(BLOCKED_REDIRECT_PAGE)
+        }
+        Ok(Some(url)) => {
+            metrics.record_redirect(RedirectOutcome::Hit);
+            click_recorder.record(ClickEvent::from_request(&url, &request, secret.ip_hash_salt.as_ref(), &application.trusted_proxies));
+            let redirect_type = url.redirect_type.unwrap_or(application.default_redirect_type);
+            HttpResponse::build(redirect_type.status_code())
+                .append_header(("Location", url.original_url.as_str()))
+                .append_header((
+                    "Cache-Control",
+                    redirect_type.cache_control(application.permanent_redirect_max_age_secs),
+                ))
+                .finish()
+        }
+        Ok(None) => {
+            metrics.record_redirect(RedirectOutcome::Miss);
+            HttpResponse::NotFound().finish()
+        }
+        Err(_) => {
+            metrics.record_redirect(RedirectOutcome::Error);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/links",
+    tag = "Manage short URLs",
+    responses(
+        (status=200, description= "List short URLs of the user's organization", body= GenericResponse<UrlListData>),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ListUrlQuery,
+    )
+)]
+#[tracing::instrument(name = "list_short_urls", skip(pool))]
+pub async fn list_short_urls(
+    pool: web::Data<PgPool>,
+    query: ListUrlQuery,
+    request: HttpRequest,
+    application: web::Data<ApplicationSettings>,
+) -> Result<web::Json<GenericResponse<UrlListData>>, GenericError> {
+    let user = get_request_user(&request)?;
+    list_links(&pool, Some(&user.organization_id), &query, &application).await
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/links/{code}",
+    tag = "Manage short URLs",
+    responses(
+        (status=200, description= "Fetch a short URL of the user's organization", body= GenericResponse<UrlData>),
+        (status=404, description= "Short URL not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("code" = String, Path, description = "Short URL code"),
+    )
+)]
+#[tracing::instrument(name = "get_short_url", skip(pool))]
+pub async fn get_short_url(
+    pool: web::Data<PgPool>,
+    code: web::Path<String>,
+    request: HttpRequest,
+    application: web::Data<ApplicationSettings>,
+) -> Result<web::Json<GenericResponse<UrlData>>, GenericError> {
+    let user = get_request_user(&request)?;
+    get_link(&pool, &code, Some(&user.organization_id), &application).await
+}
+
+
+
+#[utoipa::path(
+    patch,
+    path = "/links/{code}",
+    tag = "Manage short URLs",
+    request_body(content = UpdateUrlRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Update a short URL of the user's organization", body= GenericResponse<UrlData>),
+        (status=404, description= "Short URL not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("code" = String, Path, description = "Short URL code"),
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "update_short_url", skip(pool, redirect_cache, domain_policy, resolver))]
+pub async fn update_short_url(
+    pool: web::Data<PgPool>,
+    code: web::Path<String>,
+    req: UpdateUrlRequest,
+    request: HttpRequest,
+    application: web::Data<ApplicationSettings>,
+    redirect_cache: web::Data<RedirectCache>,
+    domain_policy: web::Data<DomainPolicy>,
+    resolver: web::Data<DestinationResolver>,
+) -> Result<web::Json<GenericResponse<UrlData>>, GenericError> {
+    let user = get_request_user(&request)?;
+    let organization = fetch_member_organization(&pool, &user).await?;
+    update_link(&pool, &code, Some(&organization), req, &application, &redirect_cache, &domain_policy, &resolver).await
+}
+
+
+
+#[utoipa::path(
+    delete,
+    path = "/links/{code}",
+    tag = "Manage short URLs",
+    responses(
+        (status=200, description= "Delete a short URL of the user's organization"),
+        (status=404, description= "Short URL not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("code" = String, Path, description = "Short URL code"),
+    )
+)]
+#[tracing::instrument(name = "delete_short_url", skip(pool, redirect_cache))]
+pub async fn delete_short_url(
+    pool: web::Data<PgPool>,
+    code: web::Path<String>,
+    request: HttpRequest,
+    redirect_cache: web::Data<RedirectCache>,
+) -> Result<web::Json<GenericResponse<()>>, GenericError> {
+    let user = get_request_user(&request)?;
+    delete_link(&pool, &code, Some(&user.organization_id), &redirect_cache).await
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/links/{code}/stats",
+    tag = "Manage short URLs",
+    responses(
+        (status=200, description= "Click statistics of a short URL of the user's organization", body= GenericResponse<UrlStatsData>),
+        (status=404, description= "Short URL not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("code" = String, Path, description = "Short URL code"),
+        UrlStatsQuery,
+    )
+)]
+#[tracing::instrument(name = "get_short_url_stats", skip(pool))]
+pub async fn get_short_url_stats(
+    pool: web::Data<PgPool>,
+    code: web::Path<String>,
+    query: UrlStatsQuery,
+    request: HttpRequest,
+) -> Result<web::Json<GenericResponse<UrlStatsData>>, GenericError> {
+    let user = get_request_user(&request)?;
+    let (from, to, interval) = stats_range(query)?;
+
+    let url = match fetch_org_short_url(&pool, &code, Some(&user.organization_id)).await {
+        Ok(Some(url)) => url,
+        Ok(None) => return Err(GenericError::NotFound("Short URL not found".to_string())),
+        Err(e) => return Err(GenericError::UnexpectedError(e.into())),
+    };
+    let (total_clicks, unique_visitors) = fetch_click_totals(&pool, Some(url.id), from, to).await?;
+    let clicks_over_time = fetch_clicks_over_time(&pool, Some(url.id), from, to, &interval).await?;
+    let mut data = UrlStatsData {
+        code: url.short_url,
+        from,
+        to,
+        interval,
+        total_clicks,
+        unique_visitors,
+        clicks_over_time,
+        top_referrers: vec![],
+        top_user_agents: vec![],
+        top_browsers: vec![],
+        top_os: vec![],
+        languages: vec![],
+    };
+    for (dimension, count) in fetch_click_breakdowns(&pool, url.id, from, to, STATS_TOP_LIMIT).await? {
+        match dimension.as_str() {
+            "referrer" => data.top_referrers.push(count),
+            "user_agent" => data.top_user_agents.push(count),
+            "browser" => data.top_browsers.push(count),
+            "os" => data.top_os.push(count),
+            "language" => data.languages.push(count),
+            _ => {}
+        }
+    }
+    Ok(web::Json(GenericResponse::success(
+        "Successfully fetched short url stats",
+        Some(data),
+    )))
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/admin/domain-rules",
+    tag = "Admin",
+    responses(
+        (status=200, description= "List destination domain rules", body= GenericResponse<Vec<DomainRuleData>>),
+        (status=403, description= "Admin access required"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+    )
+)]
+#[tracing::instrument(name = "list_domain_rules", skip(pool))]
+pub async fn list_domain_rules(
+    pool: web::Data<PgPool>,
+) -> Result<web::Json<GenericResponse<Vec<DomainRuleData>>>, GenericError> {
+    let rules = fetch_domain_rules(&pool).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully fetched domain rules",
+        Some(rules.into_iter().map(|rule| rule.into_schema()).collect()),
+    )))
+}
+
+
+
+#[utoipa::path(
+    post,
+    path = "/admin/domain-rules",
+    tag = "Admin",
+    request_body(content = CreateDomainRuleRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Add a destination domain rule", body= GenericResponse<DomainRuleData>),
+        (status=403, description= "Admin access required"),
+        (status=409, description= "Rule already exists"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+    )
+)]
+#[tracing::instrument(name = "create_domain_rule", skip(pool, domain_policy))]
+pub async fn create_domain_rule(
+    pool: web::Data<PgPool>,
+    req: CreateDomainRuleRequest,
+    request: HttpRequest,
+    application: web::Data<ApplicationSettings>,
+    domain_policy: web::Data<DomainPolicy>,
+) -> Result<web::Json<GenericResponse<DomainRuleData>>, GenericError> {
+    let admin_id = get_request_user_id(&request)?;
+    let pattern = normalize_domain_pattern(&req.pattern)?;
+    match insert_domain_rule(&pool, &pattern, req.rule_type, &admin_id).await? {
+        Some(rule) => {
+            domain_policy.reload(&pool).await?;
+            Ok(web::Json(GenericResponse::success(
+                "Successfully created domain rule",
+                Some(rule.into_schema()),
+            )))
+        }
+        None => Err(GenericError::Conflict(format!(
+            "Rule for '{}' already exists",
+            pattern
+        ))),
+    }
+}
+
+
+
+#[utoipa::path(
+    delete,
+    path = "/admin/domain-rules/{id}",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Remove a destination domain rule"),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "Rule not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = i32, Path, description = "Domain rule id"),
+    )
+)]
+#[tracing::instrument(name = "remove_domain_rule", skip(pool, domain_policy))]
+pub async fn remove_domain_rule(
+    pool: web::Data<PgPool>,
+    id: web::Path<i32>,
+    domain_policy: web::Data<DomainPolicy>,
+) -> Result<web::Json<GenericResponse<()>>, GenericError> {
+    if !delete_domain_rule(&pool, *id).await? {
+        return Err(GenericError::NotFound("Domain rule not found".to_string()));
+    }
+    domain_policy.reload(&pool).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully deleted domain rule",
+        Some(()),
+    )))
+}
+
+
+
+#[utoipa::path(
+    post,
+    path = "/auth/refresh",
+    tag = "Authentication",
+    request_body(content = RefreshTokenRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Exchange a refresh token for a new access token and refresh token", body= GenericResponse<TokenPairData>),
+        (status=401, description= "Refresh token is invalid, expired, revoked or was already used"),
+    )
+)]
+#[tracing::instrument(name = "refresh_token", skip_all)]
+pub async fn refresh_token(
+    pool: web::Data<PgPool>,
+    req: RefreshTokenRequest,
+    secret: web::Data<SecretSetting>,
+    jwt_keys: web::Data<JwtKeys>,
+) -> Result<web::Json<GenericResponse<TokenPairData>>, GenericError> {
+    let tokens = rotate_refresh_token(&pool, req.refresh_token.trim(), &secret.jwt, &jwt_keys).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully refreshed token",
+        Some(tokens),
+    )))
+}
+
+
+
+#[utoipa::path(
+    post,
+    path = "/auth/revoke",
+    tag = "Authentication",
+    request_body(content = RefreshTokenRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Revoke a refresh token and every token rotated from the same session"),
+        (status=404, description= "Refresh token not found or already revoked"),
+    )
+)]
+#[tracing::instrument(name = "revoke_token", skip_all)]
+pub async fn revoke_token(
+    pool: web::Data<PgPool>,
+    req: RefreshTokenRequest,
+) -> Result<web::Json<GenericResponse<()>>, GenericError> {
+    if !revoke_refresh_token(&pool, req.refresh_token.trim()).await? {
+        return Err(GenericError::NotFound("Refresh token not found".to_string()));
+    }
+    Ok(web::Json(GenericResponse::success(
+        "Successfully revoked refresh token",
+        Some(()),
+    )))
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    tag = "Authentication",
+    responses(
+        (status=200, description= "JSON Web Key Set with the public keys that verify access tokens", content_type = "application/json"),
+    )
+)]
+#[tracing::instrument(name = "get_jwks", skip_all)]
+pub async fn get_jwks(jwt_keys: web::Data<JwtKeys>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((actix_web::http::header::CACHE_CONTROL, "public, max-age=300"))
+        .json(jwt_keys.jwks())
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "Health",
+    responses(
+        (status=200, description= "The process is up and serving requests"),
+    )
+)]
+pub async fn get_liveness() -> web::Json<GenericResponse<()>> {
+    web::Json(GenericResponse::success("Alive", Some(())))
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "Health",
+    responses(
+        (status=200, description= "The database answers and is migrated", body= GenericResponse<ReadinessData>),
+        (status=503, description= "The database is unreachable or not migrated yet", body= GenericResponse<ReadinessData>),
+    )
+)]
+pub async fn get_readiness(pool: web::Data<PgPool>) -> HttpResponse {
+    let readiness = check_readiness(&pool).await;
+    if readiness.ready {
+        HttpResponse::Ok().json(GenericResponse::success("Ready", Some(readiness)))
+    } else {
+        HttpResponse::ServiceUnavailable().json(GenericResponse::error("Not ready", "503", Some(readiness)))
+    }
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "Health",
+    responses(
+        (status=200, description= "Metrics of this instance in the Prometheus text format", content_type = "text/plain"),
+        (status=403, description= "Admin access required"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token or admin API key"),
+    )
+)]
+pub async fn get_metrics(pool: web::Data<PgPool>, metrics: web::Data<Metrics>) -> Result<HttpResponse, GenericError> {
+    let body = metrics.render(&pool)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(body))
+}
+
+
+
+#[utoipa::path(
+    post,
+    path = "/api-keys",
+    tag = "API keys",
+    request_body(content = CreateApiKeyRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Create an API key, the key is only shown in this response", body= GenericResponse<CreateApiKeyResponseData>),
+        (status=403, description= "Read-only users can't manage API keys"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+    )
+)]
+#[tracing::instrument(name = "create_api_key", skip(pool))]
+pub async fn create_api_key(
+    pool: web::Data<PgPool>,
+    req: CreateApiKeyRequest,
+    request: HttpRequest,
+) -> Result<web::Json<GenericResponse<CreateApiKeyResponseData>>, GenericError> {
+    let user_id = get_request_user_id(&request)?;
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(GenericError::ValidationError("Name must not be empty".to_string()));
+    }
+    if req.expiry_date.is_some_and(|expiry_date| expiry_date <= Utc::now()) {
+        return Err(GenericError::ValidationError("Expiry date must be in the future".to_string()));
+    }
+
+    let (key, key_prefix) = generate_api_key();
+    let api_key = insert_api_key(&pool, &user_id, name, &key_prefix, &hash_api_key(&key), req.expiry_date).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully created api key",
+        Some(CreateApiKeyResponseData {
+            key,
+            api_key: api_key.into_schema(),
+        }),
+    )))
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/api-keys",
+    tag = "API keys",
+    responses(
+        (status=200, description= "List API keys of the user", body= GenericResponse<Vec<ApiKeyData>>),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+    )
+)]
+#[tracing::instrument(name = "list_api_keys", skip(pool))]
+pub async fn list_api_keys(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<web::Json<GenericResponse<Vec<ApiKeyData>>>, GenericError> {
+    let user_id = get_request_user_id(&request)?;
+    let api_keys = list_user_api_keys(&pool, &user_id).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully fetched api keys",
+        Some(api_keys.into_iter().map(|api_key| api_key.into_schema()).collect()),
+    )))
+}
+
+
+
+#[utoipa::path(
+    delete,
+    path = "/api-keys/{id}",
+    tag = "API keys",
+    responses(
+        (status=200, description= "Revoke an API key of the user"),
+        (status=403, description= "Read-only users can't manage API keys"),
+        (status=404, description= "API key not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "API key id"),
+    )
+)]
+#[tracing::instrument(name = "revoke_api_key", skip(pool))]
+pub async fn revoke_api_key(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+    request: HttpRequest,
+) -> Result<web::Json<GenericResponse<()>>, GenericError> {
+    let user_id = get_request_user_id(&request)?;
+    if !revoke_user_api_key(&pool, &id, &user_id).await? {
+        return Err(GenericError::NotFound("API key not found".to_string()));
     }
+    Ok(web::Json(GenericResponse::success(
+        "Successfully revoked api key",
+        Some(()),
+    )))
+}
 
+
+
+#[utoipa::path(
+    get,
+    path = "/organization",
+    tag = "Organization",
+    responses(
+        (status=200, description= "Fetch the organization of the user and its settings", body= GenericResponse<OrganizationData>),
+        (status=404, description= "Organization not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+    )
+)]
+#[tracing::instrument(name = "get_own_organization", skip(pool))]
+pub async fn get_own_organization(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<web::Json<GenericResponse<OrganizationData>>, GenericError> {
+    let user = get_request_user(&request)?;
+    let organization = fetch_member_organization(&pool, &user).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully fetched organization",
+        Some(organization.into_schema()),
+    )))
 }
 
+
+
+#[utoipa::path(
+    get,
+    path = "/me/usage",
+    tag = "Usage",
+    responses(
+        (status=200, description= "Fetch the link usage of the user and its organization against their quotas", body= GenericResponse<LinkUsageData>),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+    )
+)]
+#[tracing::instrument(name = "get_own_usage", skip(pool))]
+pub async fn get_own_usage(
+    pool: web::Data<PgPool>,
+    request: HttpRequest,
+) -> Result<web::Json<GenericResponse<LinkUsageData>>, GenericError> {
+    let user = get_request_user(&request)?;
+    let usage = fetch_link_usage(&pool, &user).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully fetched usage",
+        Some(usage),
+    )))
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/admin/organizations",
+    tag = "Admin",
+    responses(
+        (status=200, description= "List organizations", body= GenericResponse<Vec<OrganizationData>>),
+        (status=403, description= "Admin access required"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+    )
+)]
+#[tracing::instrument(name = "list_organization_accounts", skip(pool))]
+pub async fn list_organization_accounts(
+    pool: web::Data<PgPool>,
+) -> Result<web::Json<GenericResponse<Vec<OrganizationData>>>, GenericError> {
+    let organizations = list_organizations(&pool).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully fetched organizations",
+        Some(organizations.into_iter().map(|organization| organization.into_schema()).collect()),
+    )))
+}
+
+
+
+#[utoipa::path(
+    post,
+    path = "/admin/organizations",
+    tag = "Admin",
+    request_body(content = CreateOrganizationRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Create an organization", body= GenericResponse<OrganizationData>),
+        (status=403, description= "Admin access required"),
+        (status=409, description= "Organization already exists"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+    )
+)]
+#[tracing::instrument(name = "create_organization_account", skip(pool))]
+pub async fn create_organization_account(
+    pool: web::Data<PgPool>,
+    mut req: CreateOrganizationRequest,
+) -> Result<web::Json<GenericResponse<OrganizationData>>, GenericError> {
+    validate_create_organization_request(&mut req)?;
+    let organization = insert_organization(&pool, &req).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully created organization",
+        Some(organization.into_schema()),
+    )))
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/admin/organizations/{id}",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Fetch an organization", body= GenericResponse<OrganizationData>),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "Organization not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "Organization id"),
+    )
+)]
+#[tracing::instrument(name = "get_organization_account", skip(pool))]
+pub async fn get_organization_account(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<GenericResponse<OrganizationData>>, GenericError> {
+    match fetch_organization(&pool, &id).await? {
+        Some(organization) => Ok(web::Json(GenericResponse::success(
+            "Successfully fetched organization",
+            Some(organization.into_schema()),
+        ))),
+        None => Err(GenericError::NotFound("Organization not found".to_string())),
+    }
+}
+
+
+
+#[utoipa::path(
+    patch,
+    path = "/admin/organizations/{id}",
+    tag = "Admin",
+    request_body(content = UpdateOrganizationRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Update an organization and its settings", body= GenericResponse<OrganizationData>),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "Organization not found"),
+        (status=409, description= "Organization name is already taken"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "Organization id"),
+    )
+)]
+#[tracing::instrument(name = "update_organization_account", skip(pool))]
+pub async fn update_organization_account(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+    mut req: UpdateOrganizationRequest,
+) -> Result<web::Json<GenericResponse<OrganizationData>>, GenericError> {
+    if req.name.is_none()
+        && req.default_expiry_days.is_none()
+        && req.allowed_domains.is_none()
+        && req.short_url_length.is_none()
+    {
+        return Err(GenericError::ValidationError("Nothing to update".to_string()));
+    }
+    validate_update_organization_request(&mut req)?;
+    match update_organization(&pool, &id, &req).await? {
+        Some(organization) => Ok(web::Json(GenericResponse::success(
+            "Successfully updated organization",
+            Some(organization.into_schema()),
+        ))),
+        None => Err(GenericError::NotFound("Organization not found".to_string())),
+    }
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/admin/organizations/{id}/quota",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Fetch the link quota shared by the members of an organization", body= GenericResponse<LinkQuotaData>),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "Organization not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "Organization id"),
+    )
+)]
+#[tracing::instrument(name = "get_organization_link_quota", skip(pool))]
+pub async fn get_organization_link_quota(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<GenericResponse<LinkQuotaData>>, GenericError> {
+    if fetch_organization(&pool, &id).await?.is_none() {
+        return Err(GenericError::NotFound("Organization not found".to_string()));
+    }
+    let quota = fetch_link_quota(pool.get_ref(), QuotaSubject::Organization(*id)).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully fetched organization quota",
+        Some(quota),
+    )))
+}
+
+
+
+#[utoipa::path(
+    put,
+    path = "/admin/organizations/{id}/quota",
+    tag = "Admin",
+    request_body(content = SetLinkQuotaRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Replace the link quota shared by the members of an organization", body= GenericResponse<LinkQuotaData>),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "Organization not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "Organization id"),
+    )
+)]
+#[tracing::instrument(name = "set_organization_link_quota", skip(pool))]
+pub async fn set_organization_link_quota(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+    req: SetLinkQuotaRequest,
+) -> Result<web::Json<GenericResponse<LinkQuotaData>>, GenericError> {
+    validate_set_link_quota_request(&req)?;
+    match set_link_quota(&pool, QuotaSubject::Organization(*id), &req).await? {
+        Some(quota) => Ok(web::Json(GenericResponse::success(
+            "Successfully updated organization quota",
+            Some(quota),
+        ))),
+        None => Err(GenericError::NotFound("Organization not found".to_string())),
+    }
+}
+
+
+
+#[utoipa::path(
+    delete,
+    path = "/admin/organizations/{id}",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Delete an organization without members together with its links"),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "Organization not found"),
+        (status=409, description= "Organization still has members"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "Organization id"),
+    )
+)]
+#[tracing::instrument(name = "delete_organization_account", skip(pool, redirect_cache))]
+pub async fn delete_organization_account(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+    redirect_cache: web::Data<RedirectCache>,
+) -> Result<web::Json<GenericResponse<()>>, GenericError> {
+    if !delete_organization(&pool, &id).await? {
+        return Err(GenericError::NotFound("Organization not found".to_string()));
+    }
+    redirect_cache.invalidate_all();
+    Ok(web::Json(GenericResponse::success(
+        "Successfully deleted organization",
+        Some(()),
+    )))
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    tag = "Admin",
+    responses(
+        (status=200, description= "List user accounts", body= GenericResponse<UserListData>),
+        (status=403, description= "Admin access required"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ListUserQuery,
+    )
+)]
+#[tracing::instrument(name = "list_user_accounts", skip(pool))]
+pub async fn list_user_accounts(
+    pool: web::Data<PgPool>,
+    query: ListUserQuery,
+) -> Result<web::Json<GenericResponse<UserListData>>, GenericError> {
+    let (page, limit, offset) = page_offset(query.page, query.limit)?;
+    let (users, total) = list_users(&pool, limit, offset).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully fetched users",
+        Some(UserListData {
+            users: users.into_iter().map(|user| user.into_schema()).collect(),
+            total,
+            page,
+            limit,
+        }),
+    )))
+}
+
+
+
+#[utoipa::path(
+    post,
+    path = "/admin/users",
+    tag = "Admin",
+    request_body(content = CreateUserRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Create a user account", body= GenericResponse<UserData>),
+        (status=403, description= "Admin access required"),
+        (status=409, description= "Username is already taken"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+    )
+)]
+#[tracing::instrument(name = "create_user_account", skip(pool))]
+pub async fn create_user_account(
+    pool: web::Data<PgPool>,
+    req: CreateUserRequest,
+) -> Result<web::Json<GenericResponse<UserData>>, GenericError> {
+    let username = req.username.trim();
+    validate_username(username)?;
+    let user = insert_user(&pool, username, &req.organization_id, req.role).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully created user",
+        Some(user.into_schema()),
+    )))
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/admin/users/{id}",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Fetch a user account", body= GenericResponse<UserData>),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "User not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "User id"),
+    )
+)]
+#[tracing::instrument(name = "get_user_account", skip(pool))]
+pub async fn get_user_account(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<GenericResponse<UserData>>, GenericError> {
+    match fetch_user(&pool, &id).await? {
+        Some(user) => Ok(web::Json(GenericResponse::success(
+            "Successfully fetched user",
+            Some(user.into_schema()),
+        ))),
+        None => Err(GenericError::NotFound("User not found".to_string())),
+    }
+}
+
+
+
+#[utoipa::path(
+    patch,
+    path = "/admin/users/{id}",
+    tag = "Admin",
+    request_body(content = UpdateUserRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Update a user account", body= GenericResponse<UserData>),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "User not found"),
+        (status=409, description= "Username is already taken"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "User id"),
+    )
+)]
+#[tracing::instrument(name = "update_user_account", skip(pool, redirect_cache))]
+pub async fn update_user_account(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+    mut req: UpdateUserRequest,
+    redirect_cache: web::Data<RedirectCache>,
+) -> Result<web::Json<GenericResponse<UserData>>, GenericError> {
+    if req.username.is_none() && req.organization_id.is_none() && req.is_active.is_none() && req.role.is_none() {
+        return Err(GenericError::ValidationError("Nothing to update".to_string()));
+    }
+    if let Some(username) = &req.username {
+        let username = username.trim().to_string();
+        validate_username(&username)?;
+        req.username = Some(username);
+    }
+
+    match update_user(&pool, &id, &req).await? {
+        Some(user) => {
+            if req.is_active.is_some() {
+                redirect_cache.invalidate_all();
+            }
+            Ok(web::Json(GenericResponse::success(
+                "Successfully updated user",
+                Some(user.into_schema()),
+            )))
+        }
+        None => Err(GenericError::NotFound("User not found".to_string())),
+    }
+}
+
+
+
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/deactivate",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Deactivate a user account, its tokens, API keys and links stop working", body= GenericResponse<UserData>),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "User not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "User id"),
+    )
+)]
+#[tracing::instrument(name = "deactivate_user_account", skip(pool, redirect_cache))]
+pub async fn deactivate_user_account(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+    redirect_cache: web::Data<RedirectCache>,
+) -> Result<web::Json<GenericResponse<UserData>>, GenericError> {
+    let req = UpdateUserRequest {
+        username: None,
+        organization_id: None,
+        is_active: Some(false),
+        role: None,
+    };
+    match update_user(&pool, &id, &req).await? {
+        Some(user) => {
+            redirect_cache.invalidate_all();
+            Ok(web::Json(GenericResponse::success(
+                "Successfully deactivated user",
+                Some(user.into_schema()),
+            )))
+        }
+        None => Err(GenericError::NotFound("User not found".to_string())),
+    }
+}
+
+
+
+#[utoipa::path(
+    post,
+    path = "/admin/users/{id}/revoke-tokens",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Revoke every access and refresh token of a user, API keys are not affected"),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "User not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "User id"),
+    )
+)]
+#[tracing::instrument(name = "revoke_user_account_tokens", skip(pool))]
+pub async fn revoke_user_account_tokens(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<GenericResponse<()>>, GenericError> {
+    if !revoke_user_tokens(&pool, &id).await? {
+        return Err(GenericError::NotFound("User not found".to_string()));
+    }
+    Ok(web::Json(GenericResponse::success(
+        "Successfully revoked user tokens",
+        Some(()),
+    )))
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/admin/users/{id}/quota",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Fetch the link quota of a user", body= GenericResponse<LinkQuotaData>),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "User not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "User id"),
+    )
+)]
+#[tracing::instrument(name = "get_user_link_quota", skip(pool))]
+pub async fn get_user_link_quota(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+) -> Result<web::Json<GenericResponse<LinkQuotaData>>, GenericError> {
+    if fetch_user(&pool, &id).await?.is_none() {
+        return Err(GenericError::NotFound("User not found".to_string()));
+    }
+    let quota = fetch_link_quota(pool.get_ref(), QuotaSubject::User(*id)).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully fetched user quota",
+        Some(quota),
+    )))
+}
+
+
+
+#[utoipa::path(
+    put,
+    path = "/admin/users/{id}/quota",
+    tag = "Admin",
+    request_body(content = SetLinkQuotaRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Replace the link quota of a user, on top of the quota of its organization", body= GenericResponse<LinkQuotaData>),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "User not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "User id"),
+    )
+)]
+#[tracing::instrument(name = "set_user_link_quota", skip(pool))]
+pub async fn set_user_link_quota(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+    req: SetLinkQuotaRequest,
+) -> Result<web::Json<GenericResponse<LinkQuotaData>>, GenericError> {
+    validate_set_link_quota_request(&req)?;
+    match set_link_quota(&pool, QuotaSubject::User(*id), &req).await? {
+        Some(quota) => Ok(web::Json(GenericResponse::success(
+            "Successfully updated user quota",
+            Some(quota),
+        ))),
+        None => Err(GenericError::NotFound("User not found".to_string())),
+    }
+}
+
+
+
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{id}",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Delete a user account together with its API keys, links stay with the organization"),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "User not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "User id"),
+    )
+)]
+#[tracing::instrument(name = "delete_user_account", skip(pool, redirect_cache))]
+pub async fn delete_user_account(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+    redirect_cache: web::Data<RedirectCache>,
+) -> Result<web::Json<GenericResponse<()>>, GenericError> {
+    if !delete_user(&pool, &id).await? {
+        return Err(GenericError::NotFound("User not found".to_string()));
+    }
+    redirect_cache.invalidate_all();
+    Ok(web::Json(GenericResponse::success(
+        "Successfully deleted user",
+        Some(()),
+    )))
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/admin/users/{id}/links",
+    tag = "Admin",
+    responses(
+        (status=200, description= "List short URLs created by a user", body= GenericResponse<UrlListData>),
+        (status=403, description= "Admin access required"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("id" = Uuid, Path, description = "User id"),
+        ListUrlQuery,
+    )
+)]
+#[tracing::instrument(name = "admin_list_user_short_urls", skip(pool))]
+pub async fn admin_list_user_short_urls(
+    pool: web::Data<PgPool>,
+    id: web::Path<Uuid>,
+    mut query: ListUrlQuery,
+    application: web::Data<ApplicationSettings>,
+) -> Result<web::Json<GenericResponse<UrlListData>>, GenericError> {
+    query.created_by = Some(id.into_inner());
+    list_links(&pool, None, &query, &application).await
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/admin/links",
+    tag = "Admin",
+    responses(
+        (status=200, description= "List short URLs of all users", body= GenericResponse<UrlListData>),
+        (status=403, description= "Admin access required"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ListUrlQuery,
+    )
+)]
+#[tracing::instrument(name = "admin_list_short_urls", skip(pool))]
+pub async fn admin_list_short_urls(
+    pool: web::Data<PgPool>,
+    query: ListUrlQuery,
+    application: web::Data<ApplicationSettings>,
+) -> Result<web::Json<GenericResponse<UrlListData>>, GenericError> {
+    list_links(&pool, None, &query, &application).await
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/admin/links/{code}",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Fetch a short URL of any user", body= GenericResponse<UrlData>),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "Short URL not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("code" = String, Path, description = "Short URL code"),
+    )
+)]
+#[tracing::instrument(name = "admin_get_short_url", skip(pool))]
+pub async fn admin_get_short_url(
+    pool: web::Data<PgPool>,
+    code: web::Path<String>,
+    application: web::Data<ApplicationSettings>,
+) -> Result<web::Json<GenericResponse<UrlData>>, GenericError> {
+    get_link(&pool, &code, None, &application).await
+}
+
+
+
+#[utoipa::path(
+    patch,
+    path = "/admin/links/{code}",
+    tag = "Admin",
+    request_body(content = UpdateUrlRequest, description = "Request Body"),
+    responses(
+        (status=200, description= "Update a short URL of any user", body= GenericResponse<UrlData>),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "Short URL not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("code" = String, Path, description = "Short URL code"),
+    )
+)]
+#[tracing::instrument(name = "admin_update_short_url", skip(pool, redirect_cache, domain_policy, resolver))]
+pub async fn admin_update_short_url(
+    pool: web::Data<PgPool>,
+    code: web::Path<String>,
+    req: UpdateUrlRequest,
+    application: web::Data<ApplicationSettings>,
+    redirect_cache: web::Data<RedirectCache>,
+    domain_policy: web::Data<DomainPolicy>,
+    resolver: web::Data<DestinationResolver>,
+) -> Result<web::Json<GenericResponse<UrlData>>, GenericError> {
+    update_link(&pool, &code, None, req, &application, &redirect_cache, &domain_policy, &resolver).await
+}
+
+
+
+#[utoipa::path(
+    delete,
+    path = "/admin/links/{code}",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Delete a short URL of any user"),
+        (status=403, description= "Admin access required"),
+        (status=404, description= "Short URL not found"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        ("code" = String, Path, description = "Short URL code"),
+    )
+)]
+#[tracing::instrument(name = "admin_delete_short_url", skip(pool, redirect_cache))]
+pub async fn admin_delete_short_url(
+    pool: web::Data<PgPool>,
+    code: web::Path<String>,
+    redirect_cache: web::Data<RedirectCache>,
+) -> Result<web::Json<GenericResponse<()>>, GenericError> {
+    delete_link(&pool, &code, None, &redirect_cache).await
+}
+
+
+
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    tag = "Admin",
+    responses(
+        (status=200, description= "Usage statistics across all users", body= GenericResponse<GlobalStatsData>),
+        (status=403, description= "Admin access required"),
+    ),
+    params(
+        ("Authorization" = String, Header, description = "JWT token"),
+        UrlStatsQuery,
+    )
+)]
+#[tracing::instrument(name = "get_global_stats", skip(pool))]
+pub async fn get_global_stats(
+    pool: web::Data<PgPool>,
+    query: UrlStatsQuery,
+) -> Result<web::Json<GenericResponse<GlobalStatsData>>, GenericError> {
+    let (from, to, interval) = stats_range(query)?;
+
+    let (total_users, active_users) = count_users(&pool).await?;
+    let (total_links, active_links) = count_short_urls(&pool)
+        .await
+        .map_err(|e| GenericError::UnexpectedError(e.into()))?;
+    let (total_clicks, unique_visitors) = fetch_click_totals(&pool, None, from, to).await?;
+    let clicks_over_time = fetch_clicks_over_time(&pool, None, from, to, &interval).await?;
+    let top_links = fetch_top_links(&pool, from, to, STATS_TOP_LIMIT).await?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully fetched global stats",
+        Some(GlobalStatsData {
+            from,
+            to,
+            interval,
+            total_users,
+            active_users,
+            total_links,
+            active_links,
+            total_clicks,
+            unique_visitors,
+            clicks_over_time,
+            top_links,
+        }),
+    )))
+}
+
+
+
+async fn list_links(
+    pool: &PgPool,
+    organization_id: Option<&Uuid>,
+    query: &ListUrlQuery,
+    application: &ApplicationSettings,
+) -> Result<web::Json<GenericResponse<UrlListData>>, GenericError> {
+    let (page, limit, offset) = page_offset(query.page, query.limit)?;
+    let (urls, total) = list_org_short_urls(pool, organization_id, query, limit, offset)
+        .await
+        .map_err(|e| GenericError::UnexpectedError(e.into()))?;
+    Ok(web::Json(GenericResponse::success(
+        "Successfully fetched short urls",
+        Some(UrlListData {
+            urls: urls
+                .into_iter()
+                .map(|url| url.into_schema(&application.domain))
+                .collect(),
+            total,
+            page,
+            limit,
+        }),
+    )))
+}
+
+
+
+async fn get_link(
+    pool: &PgPool,
+    code: &str,
+    organization_id: Option<&Uuid>,
+    application: &ApplicationSettings,
+) -> Result<web::Json<GenericResponse<UrlData>>, GenericError> {
+    match fetch_org_short_url(pool, code, organization_id).await {
+        Ok(Some(url)) => Ok(web::Json(GenericResponse::success(
+            "Successfully fetched short url",
+            Some(url.into_schema(&application.domain)),
+        ))),
+        Ok(None) => Err(GenericError::NotFound("Short URL not found".to_string())),
+        Err(e) => Err(GenericError::UnexpectedError(e.into())),
+    }
+}
+
+
+
+/// Admins pass no organization, their updates are not held to any organization's allowed domains.
+#[allow(clippy::too_many_arguments)]
+async fn update_link(
+    pool: &PgPool,
+    code: &str,
+    organization: Option<&OrganizationModel>,
+    mut req: UpdateUrlRequest,
+    application: &ApplicationSettings,
+    redirect_cache: &RedirectCache,
+    domain_policy: &DomainPolicy,
+    resolver: &DestinationResolver,
+) -> Result<web::Json<GenericResponse<UrlData>>, GenericError> {
+    if req.original_url.is_none() && req.expiry_date.is_none() && req.redirect_type.is_none() {
+        return Err(GenericError::ValidationError("Nothing to update".to_string()));
+    }
+    if req.expiry_date.flatten().is_some_and(|expiry_date| expiry_date <= Utc::now()) {
+        return Err(GenericError::ValidationError("Expiry date must be in the future".to_string()));
+    }
+    if let Some(original_url) = &req.original_url {
+        let original_url = normalize_url(original_url, &application.allowed_url_schemes)?;
+        let original_url = resolver.resolve(pool, &original_url).await?;
+        check_destination_domain(&original_url, &domain_policy.current())?;
+        if let Some(organization) = organization {
+            check_organization_domain(&original_url, organization)?;
+        }
+        req.original_url = Some(original_url);
+    }
+
+    match update_org_short_url(pool, code, organization.map(|organization| &organization.id), &req).await {
+        Ok(Some(url)) => {
+            redirect_cache.invalidate(code).await;
+            Ok(web::Json(GenericResponse::success(
+                "Successfully updated short url",
+                Some(url.into_schema(&application.domain)),
+            )))
+        }
+        Ok(None) => Err(GenericError::NotFound("Short URL not found".to_string())),
+        Err(e) => Err(GenericError::UnexpectedError(e.into())),
+    }
+}
+
+
+
+async fn delete_link(
+    pool: &PgPool,
+    code: &str,
+    organization_id: Option<&Uuid>,
+    redirect_cache: &RedirectCache,
+) -> Result<web::Json<GenericResponse<()>>, GenericError> {
+    match delete_org_short_url(pool, code, organization_id).await {
+        Ok(true) => {
+            redirect_cache.invalidate(code).await;
+            Ok(web::Json(GenericResponse::success(
+                "Successfully deleted short url",
+                Some(()),
+            )))
+        }
+        Ok(false) => Err(GenericError::NotFound("Short URL not found".to_string())),
+        Err(e) => Err(GenericError::UnexpectedError(e.into())),
+    }
+}
+
+
+
+async fn fetch_member_organization(
+    pool: &PgPool,
+    user: &AuthenticatedUser,
+) -> Result<OrganizationModel, GenericError> {
+    fetch_organization(pool, &user.organization_id)
+        .await?
+        .ok_or_else(|| GenericError::NotFound("Organization not found".to_string()))
+}
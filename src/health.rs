@@ -0,0 +1,57 @@
+use std::time::{Duration, Instant};
+
+use sqlx::PgPool;
+
+use crate::schemas::{DatabaseHealthData, MigrationHealthData, PoolHealthData, ReadinessData};
+use crate::utils::{fetch_migration_version, SCHEMA_VERSION};
+
+/// Shorter than the pool's acquire timeout so a saturated pool fails the probe instead of hanging it.
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+const DATABASE_UNAVAILABLE: &str = "Database unavailable";
+
+fn pool_health(pool: &PgPool) -> PoolHealthData {
+    let size = pool.size();
+    let idle = pool.num_idle() as u32;
+    let max_connections = pool.options().get_max_connections();
+    let in_use = size.saturating_sub(idle);
+    PoolHealthData {
+        size,
+        idle,
+        in_use,
+        max_connections,
+        saturation: if max_connections == 0 { 0.0 } else { in_use as f64 / max_connections as f64 },
+    }
+}
+
+/// Ready when the database answers and every migration up to at least `SCHEMA_VERSION` was applied.
+/// A newer schema is fine, that is an older instance running during a rolling deploy.
+pub async fn check_readiness(pool: &PgPool) -> ReadinessData {
+    let pool_data = pool_health(pool);
+    let started = Instant::now();
+    let applied = tokio::time::timeout(READINESS_TIMEOUT, fetch_migration_version(pool))
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out after {:?}", READINESS_TIMEOUT))
+        .and_then(|result| result);
+    let (database, applied_version) = match applied {
+        Ok(version) => (
+            DatabaseHealthData { ok: true, latency_ms: Some(started.elapsed().as_millis() as u64), error: None },
+            version,
+        ),
+        Err(e) => {
+            // The endpoint is public, the details only go to the log.
+            tracing::warn!("Readiness check failed: {:?}", e);
+            (DatabaseHealthData { ok: false, latency_ms: None, error: Some(DATABASE_UNAVAILABLE.to_string()) }, None)
+        }
+    };
+    let migrations = MigrationHealthData {
+        ok: applied_version.is_some_and(|version| version >= SCHEMA_VERSION),
+        expected_version: SCHEMA_VERSION,
+        applied_version,
+    };
+    ReadinessData {
+        ready: database.ok && migrations.ok,
+        database,
+        pool: pool_data,
+        migrations,
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use moka::future::Cache;
+use sqlx::PgPool;
+
+use crate::errors::{CustomJWTTokenError, GenericError};
+use crate::metrics::Metrics;
+use crate::organizations::fetch_organization_by_name;
+use crate::schemas::{AuthenticatedUser, IdentityProviderSettings, UserRole};
+use crate::users::{fetch_authenticated_user_by_username, insert_user, validate_username};
+
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Unknown `kid`s trigger a refetch so keys rotated at the provider are picked up early,
+/// but at most this often so random `kid`s can't be used to hammer the provider.
+const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+enum JwksSource {
+    Url(String),
+    File(PathBuf),
+}
+
+/// Verifies JWTs minted by the central auth service and maps them to a `user_account`
+/// through the configured claim, creating the account on first login when enabled.
+pub struct IdentityProvider {
+    issuer: String,
+    audience: String,
+    user_claim: String,
+    provisioning: Option<(String, UserRole)>,
+    source: JwksSource,
+    client: reqwest::Client,
+    /// Keyed by `epoch`, concurrent lookups of the same epoch share one fetch.
+    jwks: Cache<u64, Arc<JwkSet>>,
+    /// Moved on when a `kid` is unknown, so every waiting request reads the refetched set.
+    epoch: AtomicU64,
+    last_fetch: Mutex<Option<Instant>>,
+}
+
+impl IdentityProvider {
+    pub fn new(settings: &IdentityProviderSettings) -> Result<Self, anyhow::Error> {
+        let source = match (&settings.jwks_url, &settings.jwks_file) {
+            (Some(url), None) => JwksSource::Url(url.clone()),
+            (None, Some(path)) => JwksSource::File(PathBuf::from(path)),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Exactly one of identity_provider.jwks_url and identity_provider.jwks_file must be set"
+                ))
+            }
+        };
+        let provisioning = match (settings.auto_provision, &settings.provision_organization) {
+            (false, _) => None,
+            (true, Some(organization)) => Some((organization.clone(), settings.provision_role)),
+            (true, None) => {
+                return Err(anyhow::anyhow!(
+                    "identity_provider.provision_organization is required with identity_provider.auto_provision"
+                ))
+            }
+        };
+        Ok(Self {
+            issuer: settings.issuer.clone(),
+            audience: settings.audience.clone(),
+            user_claim: settings.user_claim.clone(),
+            provisioning,
+            source,
+            client: reqwest::Client::builder().timeout(JWKS_FETCH_TIMEOUT).build()?,
+            jwks: Cache::builder()
+                .time_to_live(Duration::from_secs(settings.jwks_cache_secs))
+                .build(),
+            epoch: AtomicU64::new(0),
+            last_fetch: Mutex::new(None),
+        })
+    }
+
+    async fn fetch_jwks(&self) -> Result<Arc<JwkSet>, anyhow::Error> {
+        let body = match &self.source {
+            JwksSource::Url(url) => self
+                .client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?
+                .to_vec(),
+            JwksSource::File(path) => std::fs::read(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?,
+        };
+        let jwks: Arc<JwkSet> = Arc::new(serde_json::from_slice(&body)?);
+        *self.last_fetch.lock().unwrap() = Some(Instant::now());
+        Ok(jwks)
+    }
+
+    async fn jwks(&self, epoch: u64) -> Result<Arc<JwkSet>, anyhow::Error> {
+        self.jwks
+            .try_get_with(epoch, self.fetch_jwks())
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to fetch JWKS: {}", e))
+    }
+
+    async fn find_key(&self, kid: Option<&str>) -> Result<Option<Jwk>, anyhow::Error> {
+        let find = |jwks: &JwkSet| match kid {
+            Some(kid) => jwks.find(kid).cloned(),
+            None if jwks.keys.len() == 1 => jwks.keys.first().cloned(),
+            None => None,
+        };
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        if let Some(key) = find(&*self.jwks(epoch).await?) {
+            return Ok(Some(key));
+        }
+        {
+            let mut last_fetch = self.last_fetch.lock().unwrap();
+            // Only the first request to miss moves the epoch on, the others find it moved already.
+            if self.epoch.load(Ordering::SeqCst) == epoch
+                && last_fetch.is_none_or(|fetched| fetched.elapsed() >= JWKS_MIN_REFRESH_INTERVAL)
+            {
+                *last_fetch = Some(Instant::now());
+                self.epoch.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        let refreshed = self.epoch.load(Ordering::SeqCst);
+        if refreshed == epoch {
+            return Ok(None);
+        }
+        Ok(find(&*self.jwks(refreshed).await?))
+    }
+
+    /// Verifies signature, expiry, issuer and audience and returns the value of the user claim.
+    #[tracing::instrument(name = "verify_external_token", skip_all)]
+    pub async fn verify(&self, token: &str, metrics: Option<&Metrics>) -> Result<String, GenericError> {
+        let reject = |error: CustomJWTTokenError| {
+            if let Some(metrics) = metrics {
+                metrics.record_jwt_failure(&error);
+            }
+            GenericError::InvalidJWT(error.to_string())
+        };
+        let invalid = |message: &str| reject(CustomJWTTokenError::Invalid(message.to_string()));
+        let header = decode_header(token).map_err(|_| invalid("Invalid Token"))?;
+        let Some(jwk) = self.find_key(header.kid.as_deref()).await? else {
+            return Err(invalid("Invalid Token"));
+        };
+        if !key_accepts(&jwk, header.alg) {
+            return Err(invalid("Invalid Token"));
+        }
+        let key = DecodingKey::from_jwk(&jwk).map_err(|_| invalid("Invalid Token"))?;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        let claims = decode::<HashMap<String, serde_json::Value>>(token, &key, &validation)
+            .map_err(|e| match e.kind() {
+                jsonwebtoken::errors::ErrorKind::ExpiredSignature => reject(CustomJWTTokenError::Expired),
+                _ => invalid("Invalid Token"),
+            })?
+            .claims;
+        claims
+            .get(&self.user_claim)
+            .and_then(|value| value.as_str())
+            .map(str::to_string)
+            .ok_or_else(|| invalid(&format!("Token has no '{}' claim", self.user_claim)))
+    }
+
+    /// Resolves the token to an active user, creating the account if provisioning is enabled.
+    #[tracing::instrument(name = "authenticate_external_token", skip_all)]
+    pub async fn authenticate(
+        &self,
+        pool: &PgPool,
+        token: &str,
+        metrics: Option<&Metrics>,
+    ) -> Result<AuthenticatedUser, GenericError> {
+        let username = self.verify(token, metrics).await?;
+        if let Some(user) = fetch_authenticated_user_by_username(pool, &username).await? {
+            return Ok(user);
+        }
+        let no_account = || {
+            if let Some(metrics) = metrics {
+                metrics.record_jwt_revoked();
+            }
+            GenericError::InvalidJWT("No active user account for this token".to_string())
+        };
+        let Some((organization, role)) = &self.provisioning else {
+            return Err(no_account());
+        };
+        validate_username(&username).map_err(|_| no_account())?;
+        let organization = fetch_organization_by_name(pool, organization)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Provisioning organization '{}' does not exist", organization))?;
+        match insert_user(pool, &username, &organization.id, *role).await {
+            Ok(user) => {
+                tracing::info!(user_id = %user.id, "Provisioned user account from identity provider");
+                Ok(AuthenticatedUser { id: user.id, organization_id: user.organization_id, role: user.role })
+            }
+            // A concurrent first login created it, or the username belongs to a deactivated account.
+            Err(GenericError::Conflict(_)) => fetch_authenticated_user_by_username(pool, &username)
+                .await?
+                .ok_or_else(no_account),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Only asymmetric algorithms that fit the key type, the provider's keys are public after all.
+fn key_accepts(jwk: &Jwk, algorithm: Algorithm) -> bool {
+    if let Some(key_algorithm) = jwk.common.key_algorithm {
+        if Algorithm::from_str(&key_algorithm.to_string()).ok() != Some(algorithm) {
+            return false;
+        }
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => matches!(
+            algorithm,
+            Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::PS256 | Algorithm::PS384 | Algorithm::PS512
+        ),
+        AlgorithmParameters::EllipticCurve(_) => matches!(algorithm, Algorithm::ES256 | Algorithm::ES384),
+        AlgorithmParameters::OctetKeyPair(_) => algorithm == Algorithm::EdDSA,
+        AlgorithmParameters::OctetKey(_) => false,
+    }
+}
@@ -0,0 +1,267 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, PublicKeyUse, RSAKeyParameters,
+};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{de::DeserializeOwned, Serialize};
+use simple_asn1::{from_der, oid, ASN1Block};
+use uuid::Uuid;
+
+use crate::errors::CustomJWTTokenError;
+use crate::schemas::{JWTClaims, Jwt, UserRole};
+
+const PUBLIC_KEY_SUFFIX: &str = ".pub.pem";
+const PRIVATE_KEY_SUFFIX: &str = ".pem";
+
+struct VerificationKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Keys used to sign and verify our own JWTs.
+///
+/// Tokens without a `kid` are HS256 tokens signed with `secret.jwt.secret`, everything else is
+/// looked up by `kid` among the public keys of `secret.jwt.key_dir`. All of those public keys are
+/// published at `/.well-known/jwks.json`, so a new key can be rolled out before it signs anything
+/// and an old one kept until the last token it signed has expired.
+pub struct JwtKeys {
+    signing_key_id: Option<String>,
+    signing_algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    hmac_key: Option<DecodingKey>,
+    verification_keys: HashMap<String, VerificationKey>,
+    jwks: JwkSet,
+}
+
+impl JwtKeys {
+    /// HS256 only, the setup before asymmetric keys existed.
+    pub fn from_secret(secret: &SecretString) -> Self {
+        Self {
+            signing_key_id: None,
+            signing_algorithm: Algorithm::HS256,
+            encoding_key: EncodingKey::from_secret(secret.expose_secret().as_bytes()),
+            hmac_key: Some(DecodingKey::from_secret(secret.expose_secret().as_bytes())),
+            verification_keys: HashMap::new(),
+            jwks: JwkSet { keys: vec![] },
+        }
+    }
+
+    pub fn from_settings(jwt: &Jwt) -> Result<Self, anyhow::Error> {
+        let secret = jwt.secret.as_ref().filter(|secret| !secret.expose_secret().is_empty());
+        let Some(key_dir) = &jwt.key_dir else {
+            let secret = secret.ok_or_else(|| {
+                anyhow::anyhow!("secret.jwt.secret is required when secret.jwt.key_dir is not set")
+            })?;
+            return Ok(Self::from_secret(secret));
+        };
+        let signing_key_id = jwt
+            .signing_key_id
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("secret.jwt.signing_key_id is required when secret.jwt.key_dir is set"))?;
+
+        let jwks = load_public_keys(Path::new(key_dir))?;
+        let mut verification_keys = HashMap::new();
+        for jwk in &jwks.keys {
+            let kid = jwk.common.key_id.clone().unwrap_or_default();
+            let algorithm = jwk_algorithm(jwk)?;
+            verification_keys.insert(kid, VerificationKey { algorithm, key: DecodingKey::from_jwk(jwk)? });
+        }
+        let signing_algorithm = verification_keys
+            .get(&signing_key_id)
+            .map(|key| key.algorithm)
+            .ok_or_else(|| {
+                anyhow::anyhow!("Public key {}{} not found in {}", signing_key_id, PUBLIC_KEY_SUFFIX, key_dir)
+            })?;
+        let private_key_path = Path::new(key_dir).join(format!("{}{}", signing_key_id, PRIVATE_KEY_SUFFIX));
+        let private_key = fs::read(&private_key_path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", private_key_path.display(), e))?;
+        let encoding_key = match signing_algorithm {
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(&private_key)?,
+            Algorithm::ES256 => EncodingKey::from_ec_pem(&private_key)?,
+            _ => EncodingKey::from_ed_pem(&private_key)?,
+        };
+
+        let keys = Self {
+            signing_key_id: Some(signing_key_id),
+            signing_algorithm,
+            encoding_key,
+            hmac_key: secret.map(|secret| DecodingKey::from_secret(secret.expose_secret().as_bytes())),
+            verification_keys,
+            jwks,
+        };
+        // Catches a private key that does not belong to the published public key before any token is handed out.
+        let probe = keys.encode(&JWTClaims {
+            sub: Uuid::nil(),
+            exp: usize::MAX,
+            role: UserRole::ReadOnly,
+            ver: 0,
+        })?;
+        keys.decode::<JWTClaims>(&probe).map_err(|_| {
+            anyhow::anyhow!("Private key {} does not match its public key", private_key_path.display())
+        })?;
+        Ok(keys)
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, anyhow::Error> {
+        let mut header = Header::new(self.signing_algorithm);
+        header.kid = self.signing_key_id.clone();
+        Ok(encode(&header, claims, &self.encoding_key)?)
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<T, CustomJWTTokenError> {
+        let invalid = || CustomJWTTokenError::Invalid("Invalid Token".to_string());
+        let header = decode_header(token).map_err(|_| invalid())?;
+        // The algorithm comes from the key, never from the token, so a public key can't be used as an HMAC secret.
+        let (algorithm, key) = match &header.kid {
+            Some(kid) => self
+                .verification_keys
+                .get(kid)
+                .map(|key| (key.algorithm, &key.key))
+                .ok_or_else(invalid)?,
+            None => (Algorithm::HS256, self.hmac_key.as_ref().ok_or_else(invalid)?),
+        };
+        if header.alg != algorithm {
+            return Err(invalid());
+        }
+        match decode::<T>(token, key, &Validation::new(algorithm)) {
+            Ok(token) => Ok(token.claims),
+            Err(e) => match e.kind() {
+                jsonwebtoken::errors::ErrorKind::ExpiredSignature => Err(CustomJWTTokenError::Expired),
+                _ => Err(invalid()),
+            },
+        }
+    }
+
+    /// Whether the token claims to be signed by one of our keys. Anything else can only come
+    /// from the external identity provider.
+    pub fn is_local(&self, token: &str) -> bool {
+        match decode_header(token) {
+            Ok(header) => match &header.kid {
+                Some(kid) => self.verification_keys.contains_key(kid),
+                None => header.alg == Algorithm::HS256,
+            },
+            Err(_) => true,
+        }
+    }
+
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
+
+fn jwk_algorithm(jwk: &Jwk) -> Result<Algorithm, anyhow::Error> {
+    match jwk.common.key_algorithm {
+        Some(KeyAlgorithm::RS256) => Ok(Algorithm::RS256),
+        Some(KeyAlgorithm::ES256) => Ok(Algorithm::ES256),
+        Some(KeyAlgorithm::EdDSA) => Ok(Algorithm::EdDSA),
+        other => Err(anyhow::anyhow!("Unsupported JWT signing algorithm {:?}", other)),
+    }
+}
+
+/// Reads every `<kid>.pub.pem` of the directory, sorted by `kid`.
+fn load_public_keys(key_dir: &Path) -> Result<JwkSet, anyhow::Error> {
+    let mut keys = vec![];
+    for entry in fs::read_dir(key_dir).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", key_dir.display(), e))? {
+        let path = entry?.path();
+        let Some(kid) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(PUBLIC_KEY_SUFFIX))
+        else {
+            continue;
+        };
+        let pem = fs::read_to_string(&path)?;
+        let jwk = public_key_to_jwk(kid, &pem)
+            .map_err(|e| anyhow::anyhow!("Invalid public key {}: {}", path.display(), e))?;
+        keys.push(jwk);
+    }
+    keys.sort_by(|a, b| a.common.key_id.cmp(&b.common.key_id));
+    Ok(JwkSet { keys })
+}
+
+/// Converts a PEM encoded RSA, P-256 or Ed25519 public key into its JWK form. The algorithm
+/// follows from the key type: RS256, ES256 or EdDSA.
+pub fn public_key_to_jwk(kid: &str, pem: &str) -> Result<Jwk, anyhow::Error> {
+    let pem = pem::parse(pem)?;
+    let (key_algorithm, algorithm) = match pem.tag() {
+        "RSA PUBLIC KEY" => (KeyAlgorithm::RS256, rsa_parameters(pem.contents())?),
+        "PUBLIC KEY" => subject_public_key_parameters(pem.contents())?,
+        tag => return Err(anyhow::anyhow!("Expected a PUBLIC KEY, found {}", tag)),
+    };
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm,
+    })
+}
+
+fn subject_public_key_parameters(der: &[u8]) -> Result<(KeyAlgorithm, AlgorithmParameters), anyhow::Error> {
+    let malformed = || anyhow::anyhow!("Malformed SubjectPublicKeyInfo");
+    let blocks = from_der(der)?;
+    let Some(ASN1Block::Sequence(_, info)) = blocks.first() else {
+        return Err(malformed());
+    };
+    let (Some(ASN1Block::Sequence(_, identifier)), Some(ASN1Block::BitString(_, _, key))) = (info.first(), info.get(1))
+    else {
+        return Err(malformed());
+    };
+    let Some(ASN1Block::ObjectIdentifier(_, key_type)) = identifier.first() else {
+        return Err(malformed());
+    };
+
+    if *key_type == oid!(1, 2, 840, 113549, 1, 1, 1) {
+        Ok((KeyAlgorithm::RS256, rsa_parameters(key)?))
+    } else if *key_type == oid!(1, 2, 840, 10045, 2, 1) {
+        let p256 = matches!(identifier.get(1), Some(ASN1Block::ObjectIdentifier(_, curve)) if *curve == oid!(1, 2, 840, 10045, 3, 1, 7));
+        if !p256 || key.len() != 65 || key[0] != 0x04 {
+            return Err(anyhow::anyhow!("Only uncompressed P-256 keys are supported"));
+        }
+        Ok((
+            KeyAlgorithm::ES256,
+            AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                key_type: Default::default(),
+                curve: EllipticCurve::P256,
+                x: URL_SAFE_NO_PAD.encode(&key[1..33]),
+                y: URL_SAFE_NO_PAD.encode(&key[33..]),
+            }),
+        ))
+    } else if *key_type == oid!(1, 3, 101, 112) {
+        if key.len() != 32 {
+            return Err(malformed());
+        }
+        Ok((
+            KeyAlgorithm::EdDSA,
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: Default::default(),
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(key),
+            }),
+        ))
+    } else {
+        Err(anyhow::anyhow!("Only RSA, P-256 and Ed25519 keys are supported"))
+    }
+}
+
+/// PKCS#1 `RSAPublicKey`, a sequence of modulus and exponent.
+fn rsa_parameters(der: &[u8]) -> Result<AlgorithmParameters, anyhow::Error> {
+    let blocks = from_der(der)?;
+    let Some(ASN1Block::Sequence(_, fields)) = blocks.first() else {
+        return Err(anyhow::anyhow!("Malformed RSA public key"));
+    };
+    let (Some(ASN1Block::Integer(_, n)), Some(ASN1Block::Integer(_, e))) = (fields.first(), fields.get(1)) else {
+        return Err(anyhow::anyhow!("Malformed RSA public key"));
+    };
+    Ok(AlgorithmParameters::RSA(RSAKeyParameters {
+        key_type: Default::default(),
+        n: URL_SAFE_NO_PAD.encode(n.to_bytes_be().1),
+        e: URL_SAFE_NO_PAD.encode(e.to_bytes_be().1),
+    }))
+}
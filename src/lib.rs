@@ -8,5 +8,21 @@ pub mod startup;
 mod routes;
 mod openapi;
 mod models;
+mod generators;
+mod analytics;
+mod cache;
+mod domain_rules;
+mod destinations;
+mod api_keys;
+mod users;
+mod organizations;
+mod tokens;
+mod jwt_keys;
+mod identity_provider;
+mod rate_limit;
+mod quotas;
+mod health;
+mod metrics;
 pub mod commands;
+#[allow(clippy::module_inception)]
 mod tests;
\ No newline at end of file
@@ -5,8 +5,9 @@
 
 use rapid_url::commands::run_custom_commands;
 use rapid_url::startup::Application;
-use rapid_url::telemetry::{get_subscriber_with_jeager, init_subscriber};
+use rapid_url::telemetry::{get_subscriber_with_jeager, init_subscriber, shutdown_tracer_provider};
 use rapid_url::utils::get_configuration;
+use std::time::Duration;
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
     let args: Vec<String> = std::env::args().collect();
@@ -15,14 +16,17 @@ async fn main() -> anyhow::Result<()> {
         run_custom_commands(args).await?;
     } else {
         let configuration = get_configuration().expect("Failed to read configuration.");
-        let subscriber = get_subscriber_with_jeager(
+        let shutdown_timeout = Duration::from_secs(configuration.application.shutdown_timeout_secs);
+        let (subscriber, tracer_provider) = get_subscriber_with_jeager(
             "rapid-url".into(),
             "info".into(),
             std::io::stdout,
         );
         init_subscriber(subscriber);
         let application = Application::build(configuration).await?;
-        application.run_until_stopped().await?;
+        let result = application.run_until_stopped().await;
+        shutdown_tracer_provider(tracer_provider, shutdown_timeout).await;
+        result?;
     }
     Ok(())
 }
\ No newline at end of file
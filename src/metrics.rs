@@ -0,0 +1,142 @@
+use std::time::Duration;
+
+use prometheus::{Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use sqlx::PgPool;
+
+use crate::cache::RedirectCache;
+use crate::errors::CustomJWTTokenError;
+
+const NAMESPACE: &str = "rapid_url";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectOutcome {
+    Hit,
+    Miss,
+    Expired,
+    Blocked,
+    Error,
+}
+
+impl RedirectOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RedirectOutcome::Hit => "hit",
+            RedirectOutcome::Miss => "miss",
+            RedirectOutcome::Expired => "expired",
+            RedirectOutcome::Blocked => "blocked",
+            RedirectOutcome::Error => "error",
+        }
+    }
+}
+
+/// Prometheus metrics of this instance, rendered by `GET /metrics`.
+///
+/// Counters are updated where things happen, the gauges are read when the metrics are rendered.
+/// The redirect cache counts its lookups itself.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    redirects: IntCounterVec,
+    links_created: IntCounterVec,
+    jwt_failures: IntCounterVec,
+    pool_size: IntGauge,
+    pool_idle: IntGauge,
+    pool_in_use: IntGauge,
+    redirect_cache_lookups: IntCounterVec,
+    redirect_cache_hit_ratio: Gauge,
+}
+
+impl Metrics {
+    pub fn new(redirect_cache: &RedirectCache) -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some(NAMESPACE.to_string()), None)?;
+        let metrics = Self {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests by route, method and status"),
+                &["method", "route", "status"],
+            )?,
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "Time until the response headers were ready"),
+                &["method", "route", "status"],
+            )?,
+            redirects: IntCounterVec::new(
+                Opts::new("redirects_total", "Redirect lookups by result: hit, miss, expired, blocked or error"),
+                &["result"],
+            )?,
+            links_created: IntCounterVec::new(
+                Opts::new("links_created_total", "Short links created, by single or bulk request"),
+                &["source"],
+            )?,
+            jwt_failures: IntCounterVec::new(
+                Opts::new("jwt_auth_failures_total", "Rejected JWTs by reason: expired, invalid or revoked"),
+                &["reason"],
+            )?,
+            pool_size: IntGauge::new("db_pool_size", "Open database connections")?,
+            pool_idle: IntGauge::new("db_pool_idle_connections", "Idle database connections")?,
+            pool_in_use: IntGauge::new("db_pool_in_use_connections", "Database connections in use")?,
+            redirect_cache_lookups: redirect_cache.lookups().clone(),
+            redirect_cache_hit_ratio: Gauge::new(
+                "redirect_cache_hit_ratio",
+                "Share of redirect cache lookups answered without the database since start",
+            )?,
+            registry,
+        };
+        metrics.registry.register(Box::new(metrics.http_requests.clone()))?;
+        metrics.registry.register(Box::new(metrics.http_request_duration.clone()))?;
+        metrics.registry.register(Box::new(metrics.redirects.clone()))?;
+        metrics.registry.register(Box::new(metrics.links_created.clone()))?;
+        metrics.registry.register(Box::new(metrics.jwt_failures.clone()))?;
+        metrics.registry.register(Box::new(metrics.pool_size.clone()))?;
+        metrics.registry.register(Box::new(metrics.pool_idle.clone()))?;
+        metrics.registry.register(Box::new(metrics.pool_in_use.clone()))?;
+        metrics.registry.register(Box::new(metrics.redirect_cache_lookups.clone()))?;
+        metrics.registry.register(Box::new(metrics.redirect_cache_hit_ratio.clone()))?;
+        Ok(metrics)
+    }
+
+    /// `route` is the matched pattern, e.g. `/links/{code}`, so short codes don't blow up the label set.
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, duration: Duration) {
+        let status = status.to_string();
+        let labels = [method, route, status.as_str()];
+        self.http_requests.with_label_values(&labels).inc();
+        self.http_request_duration.with_label_values(&labels).observe(duration.as_secs_f64());
+    }
+
+    pub fn record_redirect(&self, outcome: RedirectOutcome) {
+        self.redirects.with_label_values(&[outcome.as_str()]).inc();
+    }
+
+    pub fn record_links_created(&self, source: &str, count: u64) {
+        self.links_created.with_label_values(&[source]).inc_by(count);
+    }
+
+    pub fn record_jwt_failure(&self, error: &CustomJWTTokenError) {
+        let reason = match error {
+            CustomJWTTokenError::Expired => "expired",
+            CustomJWTTokenError::Invalid(_) => "invalid",
+        };
+        self.jwt_failures.with_label_values(&[reason]).inc();
+    }
+
+    /// A valid token of a deactivated user or one issued before its tokens were revoked.
+    pub fn record_jwt_revoked(&self) {
+        self.jwt_failures.with_label_values(&["revoked"]).inc();
+    }
+
+    /// Reads the gauges and renders everything in the Prometheus text format.
+    pub fn render(&self, pool: &PgPool) -> Result<String, anyhow::Error> {
+        let size = pool.size() as i64;
+        let idle = pool.num_idle() as i64;
+        self.pool_size.set(size);
+        self.pool_idle.set(idle);
+        self.pool_in_use.set((size - idle).max(0));
+
+        let hits = self.redirect_cache_lookups.with_label_values(&["hit"]).get();
+        let lookups = hits + self.redirect_cache_lookups.with_label_values(&["miss"]).get();
+        self.redirect_cache_hit_ratio.set(if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 });
+
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
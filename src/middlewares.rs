@@ -4,13 +4,26 @@ use futures::future::LocalBoxFuture;
 use uuid::Uuid;
 use std::future::{ready, Ready};
 use std::rc::Rc;
+use std::time::Instant;
+use sqlx::PgPool;
+use crate::api_keys::authenticate_api_key;
 use crate::errors::GenericError;
-use crate::schemas::SecretSetting;
-use crate::utils::decode_token;
+use crate::identity_provider::IdentityProvider;
+use crate::jwt_keys::JwtKeys;
+use crate::metrics::Metrics;
+use crate::rate_limit::{RateLimitDecision, RateLimitScope, RateLimiter};
+use crate::schemas::{ApplicationSettings, AuthenticatedUser, UserRole};
+use crate::users::fetch_authenticated_user;
+use crate::utils::{client_ip, decode_token};
+
+const API_KEY_HEADER: &str = "X-API-Key";
+const API_KEY_SCHEME: &str = "ApiKey ";
+const BEARER_SCHEME: &str = "Bearer ";
 
 
 pub struct AuthMiddleware<S> {
     service: Rc<S>,
+    required_role: UserRole,
 }
 
 impl<S> Service<ServiceRequest> for AuthMiddleware<S>
@@ -25,31 +38,74 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let required_role = self.required_role;
+        let api_key = req
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|h| h.to_str().ok())
+            .or_else(|| {
+                req.headers()
+                    .get(http::header::AUTHORIZATION)
+                    .and_then(|h| h.to_str().ok())
+                    .and_then(|h| h.strip_prefix(API_KEY_SCHEME))
+            })
+            .map(|key| key.trim().to_string());
+        if let Some(api_key) = api_key {
+            return Box::pin(async move {
+                let pool = req.app_data::<web::Data<PgPool>>().unwrap().clone();
+                let result = match authenticate_api_key(&pool, &api_key).await {
+                    Ok(Some(user)) => authorize(user, required_role),
+                    Ok(None) => Err(GenericError::InvalidJWT("Invalid API key".to_string())),
+                    Err(e) => Err(GenericError::UnexpectedError(e)),
+                };
+                call_authorized(service, req, result).await
+            });
+        }
+
+        let authorization = req.headers().get(http::header::AUTHORIZATION);
         let token = req
             .cookie("token")
             .map(|c| c.value().to_string())
             .or_else(|| {
-                req.headers()
-                    .get(http::header::AUTHORIZATION)
-                    .map(|h| h.to_str().unwrap().split_at(7).1.to_string())
+                authorization
+                    .and_then(|h| h.to_str().ok())
+                    .and_then(|h| h.strip_prefix(BEARER_SCHEME))
+                    .map(|token| token.trim().to_string())
             });
 
-        let jwt_secret = &req
-            .app_data::<web::Data<SecretSetting>>()
-            .unwrap()
-            .jwt
-            .secret;
+        let jwt_keys = req.app_data::<web::Data<JwtKeys>>().unwrap();
 
-        if token.is_none() {
-            let error_message = "Authorization header is missing".to_string();
+        let Some(token) = token else {
+            let json_error = match authorization {
+                Some(_) => GenericError::InvalidJWT("Authorization header must be a Bearer token or an API key".to_string()),
+                None => GenericError::ValidationError("Authorization header is missing".to_string()),
+            };
             let (request, _pl) = req.into_parts();
-            let json_error = GenericError::ValidationError(error_message);
             return Box::pin(async { Ok(ServiceResponse::from_err(json_error, request)) });
+        };
+
+        if !jwt_keys.is_local(&token) {
+            if let Some(identity_provider) = req.app_data::<web::Data<IdentityProvider>>().cloned() {
+                return Box::pin(async move {
+                    let pool = req.app_data::<web::Data<PgPool>>().unwrap().clone();
+                    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+                    let result = identity_provider
+                        .authenticate(&pool, &token, metrics.as_ref().map(|metrics| metrics.get_ref()))
+                        .await
+                        .and_then(|user| authorize(user, required_role));
+                    call_authorized(service, req, result).await
+                });
+            }
         }
 
-        let uuid = match decode_token(token.unwrap(), jwt_secret) {
-            Ok(id) => id,
+        let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+        let claims = match decode_token(token, jwt_keys) {
+            Ok(claims) => claims,
             Err(e) => {
+                if let Some(metrics) = &metrics {
+                    metrics.record_jwt_failure(&e);
+                }
                 return Box::pin(async move {
                     let (request, _pl) = req.into_parts();
                     Ok(ServiceResponse::from_err(
@@ -59,18 +115,71 @@ where
                 });
             }
         };
-        req.extensions_mut().insert::<Uuid>(uuid);
-        
-        let fut = self.service.call(req);
         Box::pin(async move {
-            let res = fut.await?;
-            Ok(res)
+            let pool = req.app_data::<web::Data<PgPool>>().unwrap().clone();
+            // A demotion applies to tokens already issued, a promotion only to new ones.
+            let result = match fetch_authenticated_user(&pool, &claims.sub, claims.ver).await {
+                Ok(Some(user)) => authorize(
+                    AuthenticatedUser { role: user.role.min(claims.role), ..user },
+                    required_role,
+                ),
+                Ok(None) => {
+                    if let Some(metrics) = &metrics {
+                        metrics.record_jwt_revoked();
+                    }
+                    Err(GenericError::InvalidJWT(
+                        "Token has been revoked or the user is inactive".to_string(),
+                    ))
+                }
+                Err(e) => Err(GenericError::UnexpectedError(e)),
+            };
+            call_authorized(service, req, result).await
         })
     }
 }
 
-/// Middleware factory for requiring authentication.
-pub struct RequireAuth;
+fn authorize(user: AuthenticatedUser, required_role: UserRole) -> Result<AuthenticatedUser, GenericError> {
+    if user.role >= required_role {
+        Ok(user)
+    } else {
+        Err(GenericError::Forbidden(format!(
+            "This action requires the {} role",
+            required_role
+        )))
+    }
+}
+
+async fn call_authorized<S>(
+    service: Rc<S>,
+    req: ServiceRequest,
+    result: Result<AuthenticatedUser, GenericError>,
+) -> Result<ServiceResponse<actix_web::body::BoxBody>, Error>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<actix_web::body::BoxBody>, Error = Error>,
+{
+    match result {
+        Ok(user) => {
+            req.extensions_mut().insert::<Uuid>(user.id);
+            req.extensions_mut().insert::<AuthenticatedUser>(user);
+            service.call(req).await
+        }
+        Err(error) => {
+            let (request, _pl) = req.into_parts();
+            Ok(ServiceResponse::from_err(error, request))
+        }
+    }
+}
+
+/// Middleware factory for requiring authentication with at least the given role.
+pub struct RequireAuth {
+    required_role: UserRole,
+}
+
+impl RequireAuth {
+    pub fn new(required_role: UserRole) -> Self {
+        Self { required_role }
+    }
+}
 
 impl<S> Transform<S, ServiceRequest> for RequireAuth
 where
@@ -86,6 +195,179 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthMiddleware {
             service: Rc::new(service),
+            required_role: self.required_role,
         }))
     }
 }
+
+
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    scope: RateLimitScope,
+}
+
+impl<S> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<actix_web::body::BoxBody>, Error = Error>
+        + 'static,
+{
+    type Response = ServiceResponse<actix_web::body::BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let scope = self.scope;
+        let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+        // Create routes sit behind `RequireAuth`, the IP is only a fallback for requests without a user.
+        let user_id = match scope {
+            RateLimitScope::Create => req.extensions().get::<Uuid>().map(|id| format!("user:{}", id)),
+            RateLimitScope::Redirect => None,
+        };
+        let key = user_id.unwrap_or_else(|| {
+            let trusted_proxies = req
+                .app_data::<web::Data<ApplicationSettings>>()
+                .map(|application| application.trusted_proxies.as_slice())
+                .unwrap_or_default();
+            let client_ip = client_ip(req.peer_addr(), req.headers(), trusted_proxies);
+            format!("ip:{}", client_ip.map(|ip| ip.to_string()).unwrap_or_default())
+        });
+
+        Box::pin(async move {
+            let decision = match limiter {
+                Some(limiter) => limiter.check(scope, &key).await.unwrap_or_else(|e| {
+                    // Failing open, an unavailable limiter must not take the service down with it.
+                    tracing::error!("Rate limit check failed: {:?}", e);
+                    None
+                }),
+                None => None,
+            };
+            let mut response = match decision {
+                Some(decision) if !decision.allowed => {
+                    let (request, _pl) = req.into_parts();
+                    let error = GenericError::TooManyRequests(format!(
+                        "Rate limit exceeded, retry in {} seconds",
+                        decision.retry_after_secs
+                    ));
+                    let mut response = ServiceResponse::from_err(error, request);
+                    response.headers_mut().insert(
+                        http::header::RETRY_AFTER,
+                        http::header::HeaderValue::from(decision.retry_after_secs),
+                    );
+                    response
+                }
+                _ => service.call(req).await?,
+            };
+            if let Some(decision) = decision {
+                insert_rate_limit_headers(response.headers_mut(), &decision);
+            }
+            Ok(response)
+        })
+    }
+}
+
+fn insert_rate_limit_headers(headers: &mut http::header::HeaderMap, decision: &RateLimitDecision) {
+    for (name, value) in [
+        ("ratelimit-limit", decision.limit),
+        ("ratelimit-remaining", decision.remaining),
+        ("ratelimit-reset", decision.reset_secs),
+    ] {
+        headers.insert(
+            http::header::HeaderName::from_static(name),
+            http::header::HeaderValue::from(value),
+        );
+    }
+}
+
+/// Middleware factory for the token bucket limits of `RateLimiter`. Wrap it inside `RequireAuth`
+/// so the authenticated user is known.
+pub struct RateLimit {
+    scope: RateLimitScope,
+}
+
+impl RateLimit {
+    pub fn new(scope: RateLimitScope) -> Self {
+        Self { scope }
+    }
+}
+
+impl<S> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<actix_web::body::BoxBody>, Error = Error>
+        + 'static,
+{
+    type Response = ServiceResponse<actix_web::body::BoxBody>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            scope: self.scope,
+        }))
+    }
+}
+
+
+
+/// Middleware factory counting and timing every request for `Metrics`, labelled by the matched route.
+pub struct RecordMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RecordMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RecordMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RecordMetricsMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct RecordMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RecordMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+        let method = req.method().to_string();
+        let started = Instant::now();
+        Box::pin(async move {
+            let result = service.call(req).await;
+            if let Some(metrics) = metrics {
+                // The route is only known once the request has been routed.
+                let (route, status) = match &result {
+                    Ok(response) => (
+                        response.request().match_pattern().unwrap_or_else(|| "unmatched".to_string()),
+                        response.status(),
+                    ),
+                    Err(e) => ("unmatched".to_string(), e.as_response_error().status_code()),
+                };
+                metrics.observe_request(&method, &route, status.as_u16(), started.elapsed());
+            }
+            result
+        })
+    }
+}
@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
+use crate::schemas::{ApiKeyData, DomainRuleData, DomainRuleType, OrganizationData, RedirectType, UrlData, UserData, UserRole};
+
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct ShortUrlModel {
@@ -10,5 +12,124 @@ pub struct ShortUrlModel {
     pub short_url: String,       
     pub original_url: String,            
     pub created_on: DateTime<Utc>,   
-    pub user_id: Uuid
-}
\ No newline at end of file
+    pub user_id: Uuid,
+    pub organization_id: Option<Uuid>,
+    pub expiry_date: Option<DateTime<Utc>>,
+    pub redirect_type: Option<RedirectType>,
+}
+
+impl ShortUrlModel {
+    pub fn is_expired(&self) -> bool {
+        self.expiry_date.is_some_and(|expiry_date| expiry_date <= Utc::now())
+    }
+
+    pub fn into_schema(self, domain: &str) -> UrlData {
+        UrlData {
+            short_url: format!("https://{}/{}", domain, &self.short_url),
+            code: self.short_url,
+            original_url: self.original_url,
+            created_on: self.created_on,
+            expiry_date: self.expiry_date,
+            redirect_type: self.redirect_type,
+            created_by: self.user_id,
+        }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct DomainRuleModel {
+    pub id: i32,
+    pub pattern: String,
+    pub rule_type: DomainRuleType,
+    pub created_on: DateTime<Utc>,
+    pub created_by: Uuid,
+}
+
+impl DomainRuleModel {
+    pub fn into_schema(self) -> DomainRuleData {
+        DomainRuleData {
+            id: self.id,
+            pattern: self.pattern,
+            rule_type: self.rule_type,
+            created_on: self.created_on,
+            created_by: self.created_by,
+        }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ApiKeyModel {
+    pub id: Uuid,
+    pub name: String,
+    pub key_prefix: String,
+    pub created_on: DateTime<Utc>,
+    pub last_used_on: Option<DateTime<Utc>>,
+    pub expiry_date: Option<DateTime<Utc>>,
+    pub revoked_on: Option<DateTime<Utc>>,
+}
+
+impl ApiKeyModel {
+    pub fn into_schema(self) -> ApiKeyData {
+        ApiKeyData {
+            id: self.id,
+            name: self.name,
+            key_prefix: self.key_prefix,
+            created_on: self.created_on,
+            last_used_on: self.last_used_on,
+            expiry_date: self.expiry_date,
+            revoked_on: self.revoked_on,
+        }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct UserAccountModel {
+    pub id: Uuid,
+    pub username: String,
+    pub organization_id: Uuid,
+    pub role: UserRole,
+    pub is_active: bool,
+    pub created_on: DateTime<Utc>,
+    pub deactivated_on: Option<DateTime<Utc>>,
+}
+
+impl UserAccountModel {
+    pub fn into_schema(self) -> UserData {
+        UserData {
+            id: self.id,
+            username: self.username,
+            organization_id: self.organization_id,
+            role: self.role,
+            is_active: self.is_active,
+            created_on: self.created_on,
+            deactivated_on: self.deactivated_on,
+        }
+    }
+}
+
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct OrganizationModel {
+    pub id: Uuid,
+    pub name: String,
+    pub default_expiry_days: Option<i32>,
+    pub allowed_domains: Vec<String>,
+    pub short_url_length: Option<i32>,
+    pub created_on: DateTime<Utc>,
+}
+
+impl OrganizationModel {
+    pub fn into_schema(self) -> OrganizationData {
+        OrganizationData {
+            id: self.id,
+            name: self.name,
+            default_expiry_days: self.default_expiry_days,
+            allowed_domains: self.allowed_domains,
+            short_url_length: self.short_url_length,
+            created_on: self.created_on,
+        }
+    }
+}
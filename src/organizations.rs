@@ -0,0 +1,243 @@
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use url::Url;
+use uuid::Uuid;
+
+use crate::domain_rules::{domain_matches, normalize_domain_pattern};
+use crate::errors::GenericError;
+use crate::models::OrganizationModel;
+use crate::schemas::{CreateOrganizationRequest, CreateUrlRequest, UpdateOrganizationRequest};
+
+const ORGANIZATION_NAME_MAX_LENGTH: usize = 128;
+const MIN_SHORT_URL_LENGTH: i32 = 4;
+const MAX_SHORT_URL_LENGTH: i32 = 32;
+
+fn validate_organization_name(name: &str) -> Result<(), GenericError> {
+    if name.trim().is_empty() || name.len() > ORGANIZATION_NAME_MAX_LENGTH {
+        return Err(GenericError::ValidationError(format!(
+            "Organization name must be between 1 and {} characters long",
+            ORGANIZATION_NAME_MAX_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+fn validate_default_expiry_days(days: i32) -> Result<(), GenericError> {
+    if days < 0 {
+        return Err(GenericError::ValidationError(
+            "Default expiry must not be negative".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_short_url_length(length: i32) -> Result<(), GenericError> {
+    if !(MIN_SHORT_URL_LENGTH..=MAX_SHORT_URL_LENGTH).contains(&length) {
+        return Err(GenericError::ValidationError(format!(
+            "Short URL length must be between {} and {}",
+            MIN_SHORT_URL_LENGTH, MAX_SHORT_URL_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+fn normalize_allowed_domains(domains: &mut Vec<String>) -> Result<(), GenericError> {
+    let mut normalized = domains
+        .iter()
+        .map(|domain| normalize_domain_pattern(domain))
+        .collect::<Result<Vec<_>, _>>()?;
+    normalized.sort();
+    normalized.dedup();
+    *domains = normalized;
+    Ok(())
+}
+
+/// Trims the name and brings the allowed domains to the form `domain_matches` expects.
+pub fn validate_create_organization_request(req: &mut CreateOrganizationRequest) -> Result<(), GenericError> {
+    req.name = req.name.trim().to_string();
+    validate_organization_name(&req.name)?;
+    if let Some(days) = req.default_expiry_days {
+        validate_default_expiry_days(days)?;
+    }
+    if let Some(length) = req.short_url_length {
+        validate_short_url_length(length)?;
+    }
+    normalize_allowed_domains(&mut req.allowed_domains)
+}
+
+pub fn validate_update_organization_request(req: &mut UpdateOrganizationRequest) -> Result<(), GenericError> {
+    if let Some(name) = &mut req.name {
+        *name = name.trim().to_string();
+        validate_organization_name(name)?;
+    }
+    if let Some(days) = req.default_expiry_days {
+        validate_default_expiry_days(days)?;
+    }
+    if let Some(length) = req.short_url_length.filter(|length| *length != 0) {
+        validate_short_url_length(length)?;
+    }
+    if let Some(domains) = &mut req.allowed_domains {
+        normalize_allowed_domains(domains)?;
+    }
+    Ok(())
+}
+
+pub fn check_organization_domain(url: &str, organization: &OrganizationModel) -> Result<(), GenericError> {
+    if organization.allowed_domains.is_empty() {
+        return Ok(());
+    }
+    let permitted = Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.trim_end_matches('.').to_string()))
+        .is_some_and(|host| {
+            organization
+                .allowed_domains
+                .iter()
+                .any(|pattern| domain_matches(pattern, &host))
+        });
+    if permitted {
+        Ok(())
+    } else {
+        Err(GenericError::ValidationError(format!(
+            "Destination domain is not allowed for organization '{}'",
+            organization.name
+        )))
+    }
+}
+
+/// Applies the organization's defaults to a link that is about to be created. Expects the
+/// destination to be normalized already.
+pub fn apply_organization_settings(
+    req: &mut CreateUrlRequest,
+    organization: &OrganizationModel,
+) -> Result<(), GenericError> {
+    check_organization_domain(&req.original_url, organization)?;
+    if req.expiry_date.is_none() {
+        req.expiry_date = organization
+            .default_expiry_days
+            .map(|days| Utc::now() + Duration::days(days.into()));
+    }
+    Ok(())
+}
+
+/// Length of generated codes for the organization, `default` unless it overrides it.
+pub fn organization_code_length(organization: &OrganizationModel, default: usize) -> usize {
+    organization
+        .short_url_length
+        .map_or(default, |length| length as usize)
+}
+
+fn map_organization_conflict(e: sqlx::Error, name: Option<&str>) -> GenericError {
+    match &e {
+        sqlx::Error::Database(db_error) if db_error.is_unique_violation() => GenericError::Conflict(
+            format!("Organization '{}' already exists", name.unwrap_or_default()),
+        ),
+        _ => GenericError::UnexpectedError(e.into()),
+    }
+}
+
+#[tracing::instrument(name = "insert_organization", skip(pool))]
+pub async fn insert_organization(
+    pool: &PgPool,
+    req: &CreateOrganizationRequest,
+) -> Result<OrganizationModel, GenericError> {
+    sqlx::query_as!(
+        OrganizationModel,
+        r#"INSERT INTO organization (id, name, default_expiry_days, allowed_domains, short_url_length, created_on)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, name, default_expiry_days, allowed_domains, short_url_length, created_on"#,
+        Uuid::new_v4(),
+        req.name,
+        req.default_expiry_days,
+        &req.allowed_domains,
+        req.short_url_length,
+        Utc::now()
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| map_organization_conflict(e, Some(&req.name)))
+}
+
+#[tracing::instrument(name = "list_organizations", skip(pool))]
+pub async fn list_organizations(pool: &PgPool) -> Result<Vec<OrganizationModel>, anyhow::Error> {
+    let organizations = sqlx::query_as!(
+        OrganizationModel,
+        r#"SELECT id, name, default_expiry_days, allowed_domains, short_url_length, created_on
+        FROM organization
+        ORDER BY name"#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(organizations)
+}
+
+#[tracing::instrument(name = "fetch_organization", skip(pool))]
+pub async fn fetch_organization(pool: &PgPool, id: &Uuid) -> Result<Option<OrganizationModel>, anyhow::Error> {
+    let organization = sqlx::query_as!(
+        OrganizationModel,
+        r#"SELECT id, name, default_expiry_days, allowed_domains, short_url_length, created_on
+        FROM organization WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(organization)
+}
+
+#[tracing::instrument(name = "fetch_organization_by_name", skip(pool))]
+pub async fn fetch_organization_by_name(
+    pool: &PgPool,
+    name: &str,
+) -> Result<Option<OrganizationModel>, anyhow::Error> {
+    let organization = sqlx::query_as!(
+        OrganizationModel,
+        r#"SELECT id, name, default_expiry_days, allowed_domains, short_url_length, created_on
+        FROM organization WHERE name = $1"#,
+        name
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(organization)
+}
+
+/// Only the fields present in the request are changed, see `UpdateOrganizationRequest`.
+#[tracing::instrument(name = "update_organization", skip(pool))]
+pub async fn update_organization(
+    pool: &PgPool,
+    id: &Uuid,
+    req: &UpdateOrganizationRequest,
+) -> Result<Option<OrganizationModel>, GenericError> {
+    sqlx::query_as!(
+        OrganizationModel,
+        r#"UPDATE organization SET
+            name = COALESCE($2, name),
+            default_expiry_days = CASE WHEN $3::INTEGER IS NULL THEN default_expiry_days ELSE NULLIF($3, 0) END,
+            allowed_domains = COALESCE($4, allowed_domains),
+            short_url_length = CASE WHEN $5::INTEGER IS NULL THEN short_url_length ELSE NULLIF($5, 0) END
+        WHERE id = $1
+        RETURNING id, name, default_expiry_days, allowed_domains, short_url_length, created_on"#,
+        id,
+        req.name,
+        req.default_expiry_days,
+        req.allowed_domains.as_deref(),
+        req.short_url_length
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| map_organization_conflict(e, req.name.as_deref()))
+}
+
+/// Removes the organization and its links. Fails with a conflict while it still has members.
+#[tracing::instrument(name = "delete_organization", skip(pool))]
+pub async fn delete_organization(pool: &PgPool, id: &Uuid) -> Result<bool, GenericError> {
+    let result = sqlx::query!("DELETE FROM organization WHERE id = $1", id)
+        .execute(pool)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db_error) if db_error.is_foreign_key_violation() => GenericError::Conflict(
+                "Organization still has members, move or delete them first".to_string(),
+            ),
+            _ => GenericError::UnexpectedError(e.into()),
+        })?;
+    Ok(result.rows_affected() > 0)
+}
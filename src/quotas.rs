@@ -0,0 +1,217 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Utc};
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::errors::GenericError;
+use crate::schemas::{AuthenticatedUser, LinkQuotaData, LinkUsageData, LinkUsageScopeData, QuotaUsageData, SetLinkQuotaRequest};
+
+/// First key of the advisory locks taken by `lock_link_usage`, the second one is the id.
+const LINK_QUOTA_LOCK: i32 = 1;
+
+/// Whose quota is meant, a user's own or the one shared by an organization.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuotaSubject {
+    User(Uuid),
+    Organization(Uuid),
+}
+
+pub fn validate_set_link_quota_request(req: &SetLinkQuotaRequest) -> Result<(), GenericError> {
+    if [req.max_active_links, req.max_links_per_day, req.max_links_per_month]
+        .iter()
+        .flatten()
+        .any(|limit| *limit < 0)
+    {
+        return Err(GenericError::ValidationError(
+            "Quota limits must not be negative".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Stores the quota, replacing all of its limits. Returns `None` when the user or organization does not exist.
+#[tracing::instrument(name = "set_link_quota", skip(pool))]
+pub async fn set_link_quota(
+    pool: &PgPool,
+    subject: QuotaSubject,
+    req: &SetLinkQuotaRequest,
+) -> Result<Option<LinkQuotaData>, anyhow::Error> {
+    let (user_id, organization_id) = match subject {
+        QuotaSubject::User(id) => (Some(id), None),
+        QuotaSubject::Organization(id) => (None, Some(id)),
+    };
+    let mut transaction = pool.begin().await?;
+    sqlx::query!(
+        "DELETE FROM link_quota WHERE user_id = $1 OR organization_id = $2",
+        user_id,
+        organization_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    let result = sqlx::query!(
+        r#"INSERT INTO link_quota (id, user_id, organization_id, max_active_links, max_links_per_day, max_links_per_month, updated_on)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+        Uuid::new_v4(),
+        user_id,
+        organization_id,
+        req.max_active_links,
+        req.max_links_per_day,
+        req.max_links_per_month,
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await;
+    match result {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(db_error)) if db_error.is_foreign_key_violation() => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    transaction.commit().await?;
+    Ok(Some(LinkQuotaData {
+        max_active_links: req.max_active_links,
+        max_links_per_day: req.max_links_per_day,
+        max_links_per_month: req.max_links_per_month,
+    }))
+}
+
+/// Unlimited when no quota was set.
+#[tracing::instrument(name = "fetch_link_quota", skip(executor))]
+pub async fn fetch_link_quota(executor: impl PgExecutor<'_>, subject: QuotaSubject) -> Result<LinkQuotaData, anyhow::Error> {
+    let (user_id, organization_id) = match subject {
+        QuotaSubject::User(id) => (Some(id), None),
+        QuotaSubject::Organization(id) => (None, Some(id)),
+    };
+    let quota = sqlx::query_as!(
+        LinkQuotaData,
+        r#"SELECT max_active_links, max_links_per_day, max_links_per_month
+        FROM link_quota WHERE user_id = $1 OR organization_id = $2"#,
+        user_id,
+        organization_id
+    )
+    .fetch_optional(executor)
+    .await?;
+    Ok(quota.unwrap_or_default())
+}
+
+fn start_of_day(now: DateTime<Utc>) -> DateTime<Utc> {
+    now.date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+fn start_of_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    start_of_day(now.with_day(1).unwrap())
+}
+
+/// Links the user created and links of the user's organization, against both quotas. Expired links are not active.
+#[tracing::instrument(name = "fetch_link_usage", skip(pool))]
+pub async fn fetch_link_usage(pool: &PgPool, user: &AuthenticatedUser) -> Result<LinkUsageData, anyhow::Error> {
+    count_link_usage(&mut *pool.acquire().await?, user).await
+}
+
+/// `fetch_link_usage` for link creation. Until `transaction` ends other creations for the user or
+/// the organization wait, so they can't all pass the quota check on the same count.
+#[tracing::instrument(name = "lock_link_usage", skip(transaction))]
+pub async fn lock_link_usage(
+    transaction: &mut PgConnection,
+    user: &AuthenticatedUser,
+) -> Result<LinkUsageData, anyhow::Error> {
+    // Always the organization first, creations of different users of one organization would deadlock otherwise.
+    for id in [user.organization_id, user.id] {
+        sqlx::query("SELECT pg_advisory_xact_lock($1, hashtext($2))")
+            .bind(LINK_QUOTA_LOCK)
+            .bind(id.to_string())
+            .execute(&mut *transaction)
+            .await?;
+    }
+    count_link_usage(transaction, user).await
+}
+
+async fn count_link_usage(conn: &mut PgConnection, user: &AuthenticatedUser) -> Result<LinkUsageData, anyhow::Error> {
+    let now = Utc::now();
+    let active = sqlx::query!(
+        r#"SELECT
+            count(*) FILTER (WHERE user_id = $1) AS "user!",
+            count(*) FILTER (WHERE organization_id = $2) AS "organization!"
+        FROM short_url
+        WHERE (user_id = $1 OR organization_id = $2) AND (expiry_date IS NULL OR expiry_date > $3)"#,
+        user.id,
+        user.organization_id,
+        now
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    // Creations are counted from their log, deleting links doesn't make room in the daily and monthly quotas.
+    let created = sqlx::query!(
+        r#"SELECT
+            count(*) FILTER (WHERE user_id = $1 AND created_on >= $3) AS "user_today!",
+            count(*) FILTER (WHERE user_id = $1) AS "user_month!",
+            count(*) FILTER (WHERE organization_id = $2 AND created_on >= $3) AS "organization_today!",
+            count(*) FILTER (WHERE organization_id = $2) AS "organization_month!"
+        FROM link_creation
+        WHERE (user_id = $1 OR organization_id = $2) AND created_on >= $4"#,
+        user.id,
+        user.organization_id,
+        start_of_day(now),
+        start_of_month(now)
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    let user_quota = fetch_link_quota(&mut *conn, QuotaSubject::User(user.id)).await?;
+    let organization_quota = fetch_link_quota(&mut *conn, QuotaSubject::Organization(user.organization_id)).await?;
+    let scope = |quota: LinkQuotaData, active, today, month| LinkUsageScopeData {
+        active_links: QuotaUsageData { used: active, limit: quota.max_active_links },
+        links_today: QuotaUsageData { used: today, limit: quota.max_links_per_day },
+        links_this_month: QuotaUsageData { used: month, limit: quota.max_links_per_month },
+    };
+    Ok(LinkUsageData {
+        user: scope(user_quota, active.user, created.user_today, created.user_month),
+        organization: scope(
+            organization_quota,
+            active.organization,
+            created.organization_today,
+            created.organization_month,
+        ),
+    })
+}
+
+/// Deletes creations from before the current month, no quota counts them anymore.
+pub fn spawn_link_creation_cleanup(pool: PgPool, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            if let Err(e) = delete_old_link_creations(&pool).await {
+                tracing::error!("Failed to delete old link creations: {:?}", e);
+            }
+        }
+    })
+}
+
+async fn delete_old_link_creations(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!("DELETE FROM link_creation WHERE created_on < $1", start_of_month(Utc::now()))
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Fails with `QuotaExceeded` naming the first quota that `requested` more links would exceed.
+pub fn check_link_quota(usage: &LinkUsageData, requested: i64) -> Result<(), GenericError> {
+    for (subject, scope) in [("user", &usage.user), ("organization", &usage.organization)] {
+        for (name, quota) in [
+            ("active link", &scope.active_links),
+            ("daily link", &scope.links_today),
+            ("monthly link", &scope.links_this_month),
+        ] {
+            if let Some(limit) = quota.limit {
+                if quota.used + requested > i64::from(limit) {
+                    return Err(GenericError::QuotaExceeded(format!(
+                        "Your {} has reached its {} quota of {} ({} used)",
+                        subject, name, limit, quota.used
+                    )));
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use moka::sync::Cache;
+use sqlx::PgPool;
+use tokio::task::JoinHandle;
+
+use crate::schemas::{RateLimitBackend, RateLimitSettings};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitScope {
+    /// Link creation, keyed by the authenticated user.
+    Create,
+    /// Redirects, keyed by the client IP.
+    Redirect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitRule {
+    capacity: f64,
+    refill_per_sec: f64,
+}
+
+impl RateLimitRule {
+    /// `None` when `per_minute` is 0, i.e. the limit is disabled.
+    pub fn new(per_minute: u32, burst: u32) -> Option<Self> {
+        (per_minute > 0).then(|| Self {
+            capacity: burst.max(1) as f64,
+            refill_per_sec: per_minute as f64 / 60.0,
+        })
+    }
+
+    fn refill(&self, tokens: f64, elapsed_secs: f64) -> f64 {
+        (tokens + elapsed_secs.max(0.0) * self.refill_per_sec).min(self.capacity)
+    }
+
+    fn seconds_until(&self, tokens: f64, target: f64) -> u64 {
+        ((target - tokens).max(0.0) / self.refill_per_sec).ceil() as u64
+    }
+
+    /// `tokens` is what is left in the bucket after the request was counted, or not.
+    fn decision(&self, allowed: bool, tokens: f64) -> RateLimitDecision {
+        RateLimitDecision {
+            allowed,
+            limit: self.capacity as u64,
+            remaining: tokens.max(0.0).floor() as u64,
+            reset_secs: self.seconds_until(tokens, self.capacity),
+            retry_after_secs: if allowed { 0 } else { self.seconds_until(tokens, 1.0).max(1) },
+        }
+    }
+
+    /// Time after which an untouched bucket is full again and can be forgotten.
+    fn full_refill(&self) -> Duration {
+        Duration::from_secs_f64(self.capacity / self.refill_per_sec)
+    }
+}
+
+/// Outcome of a rate limit check, mapped to the `RateLimit-*` and `Retry-After` headers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    pub reset_secs: u64,
+    pub retry_after_secs: u64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+enum RateLimitStore {
+    Memory(Cache<String, Arc<Mutex<Bucket>>>),
+    Postgres(PgPool),
+}
+
+/// Token buckets for the create and redirect limits, see `RateLimitSettings`.
+pub struct RateLimiter {
+    create: Option<RateLimitRule>,
+    redirect: Option<RateLimitRule>,
+    store: RateLimitStore,
+    idle_timeout: Duration,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings, pool: PgPool) -> Self {
+        let create = RateLimitRule::new(settings.create_per_minute, settings.create_burst);
+        let redirect = RateLimitRule::new(settings.redirect_per_minute, settings.redirect_burst);
+        let idle_timeout = [create, redirect]
+            .iter()
+            .flatten()
+            .map(RateLimitRule::full_refill)
+            .max()
+            .unwrap_or_default()
+            .max(Duration::from_secs(1));
+        let store = match settings.backend {
+            RateLimitBackend::Memory => {
+                RateLimitStore::Memory(
+                Cache::builder()
+                    .max_capacity(settings.memory_max_buckets)
+                    .time_to_idle(idle_timeout)
+                    .build(),
+            )
+            }
+            RateLimitBackend::Postgres => RateLimitStore::Postgres(pool),
+        };
+        Self { create, redirect, store, idle_timeout }
+    }
+
+    fn rule(&self, scope: RateLimitScope) -> Option<RateLimitRule> {
+        match scope {
+            RateLimitScope::Create => self.create,
+            RateLimitScope::Redirect => self.redirect,
+        }
+    }
+
+    /// Takes a token from the bucket of `key`. `None` when the scope is not limited.
+    #[tracing::instrument(name = "rate_limit_check", skip(self))]
+    pub async fn check(&self, scope: RateLimitScope, key: &str) -> Result<Option<RateLimitDecision>, anyhow::Error> {
+        let Some(rule) = self.rule(scope) else {
+            return Ok(None);
+        };
+        let key = format!("{:?}:{}", scope, key);
+        let decision = match &self.store {
+            RateLimitStore::Memory(buckets) => {
+                let bucket = buckets.get_with(key, || {
+                    Arc::new(Mutex::new(Bucket { tokens: rule.capacity, updated: Instant::now() }))
+                });
+                let mut bucket = bucket.lock().unwrap();
+                let now = Instant::now();
+                let tokens = rule.refill(bucket.tokens, now.duration_since(bucket.updated).as_secs_f64());
+                let allowed = tokens >= 1.0;
+                bucket.tokens = if allowed { tokens - 1.0 } else { tokens };
+                bucket.updated = now;
+                rule.decision(allowed, bucket.tokens)
+            }
+            RateLimitStore::Postgres(pool) => take_postgres_token(pool, &key, &rule).await?,
+        };
+        Ok(Some(decision))
+    }
+
+    /// Deletes Postgres buckets that have been full for a while. Nothing to do for the in-memory store.
+    pub fn spawn_cleanup(self: Arc<Self>, interval: Duration) -> Option<JoinHandle<()>> {
+        let RateLimitStore::Postgres(pool) = &self.store else {
+            return None;
+        };
+        let pool = pool.clone();
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = delete_idle_buckets(&pool, self.idle_timeout).await {
+                    tracing::error!("Failed to delete idle rate limit buckets: {:?}", e);
+                }
+            }
+        }))
+    }
+}
+
+/// Refill and take happen in one statement so concurrent instances can't both take the last token.
+/// A request that finds the bucket empty leaves it untouched and the row is read again for the headers.
+async fn take_postgres_token(
+    pool: &PgPool,
+    key: &str,
+    rule: &RateLimitRule,
+) -> Result<RateLimitDecision, anyhow::Error> {
+    let now = Utc::now();
+    let taken = sqlx::query_scalar!(
+        r#"INSERT INTO rate_limit_bucket AS bucket (key, tokens, updated_on)
+        VALUES ($1, $2::DOUBLE PRECISION - 1, $4)
+        ON CONFLICT (key) DO UPDATE SET
+            tokens = LEAST($2, bucket.tokens + EXTRACT(EPOCH FROM ($4 - bucket.updated_on))::DOUBLE PRECISION * $3) - 1,
+            updated_on = $4
+        WHERE LEAST($2, bucket.tokens + EXTRACT(EPOCH FROM ($4 - bucket.updated_on))::DOUBLE PRECISION * $3) >= 1
+        RETURNING tokens"#,
+        key,
+        rule.capacity,
+        rule.refill_per_sec,
+        now
+    )
+    .fetch_optional(pool)
+    .await?;
+    if let Some(tokens) = taken {
+        return Ok(rule.decision(true, tokens));
+    }
+    let bucket = sqlx::query!("SELECT tokens, updated_on FROM rate_limit_bucket WHERE key = $1", key)
+        .fetch_one(pool)
+        .await?;
+    let elapsed = (now - bucket.updated_on).num_milliseconds() as f64 / 1000.0;
+    Ok(rule.decision(false, rule.refill(bucket.tokens, elapsed)))
+}
+
+#[tracing::instrument(name = "delete_idle_buckets", skip(pool))]
+async fn delete_idle_buckets(pool: &PgPool, idle_timeout: Duration) -> Result<u64, anyhow::Error> {
+    let cutoff = Utc::now() - chrono::Duration::from_std(idle_timeout)?;
+    let result = sqlx::query!("DELETE FROM rate_limit_bucket WHERE updated_on < $1", cutoff)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
@@ -1,14 +1,73 @@
-
-use crate::handlers::{create_short_url, redirect_short_url};
-use crate::middlewares::RequireAuth;
+use crate::handlers::{admin_delete_short_url, admin_get_short_url, admin_list_short_urls, admin_list_user_short_urls, admin_update_short_url, create_api_key, create_bulk_short_urls, create_domain_rule, create_organization_account, create_short_url, create_user_account, deactivate_user_account, delete_organization_account, delete_short_url, delete_user_account, get_global_stats, get_jwks, get_liveness, get_metrics, get_organization_account, get_organization_link_quota, get_own_organization, get_own_usage, get_readiness, get_short_url, get_short_url_stats, get_user_account, get_user_link_quota, list_api_keys, list_domain_rules, list_organization_accounts, list_short_urls, list_user_accounts, redirect_short_url, refresh_token, remove_domain_rule, revoke_api_key, revoke_token, revoke_user_account_tokens, set_organization_link_quota, set_user_link_quota, update_organization_account, update_short_url, update_user_account};
+use crate::middlewares::{RateLimit, RequireAuth};
 use crate::openapi::ApiDoc;
+use crate::rate_limit::RateLimitScope;
+use crate::schemas::UserRole;
 use actix_web::web;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
     let openapi = ApiDoc::openapi();
-    cfg.route("/{short_url}", web::get().to(redirect_short_url))
-        .route("/shorten", web::post().to(create_short_url).wrap(RequireAuth))
-        .service(SwaggerUi::new("/docs/{_:.*}").url("/api-docs/openapi.json", openapi.clone()));
+    let read_only = || RequireAuth::new(UserRole::ReadOnly);
+    let member = || RequireAuth::new(UserRole::Member);
+    let create_limit = || RateLimit::new(RateLimitScope::Create);
+    cfg.route("/health/live", web::get().to(get_liveness))
+        .route("/health/ready", web::get().to(get_readiness))
+        .route("/metrics", web::get().to(get_metrics).wrap(RequireAuth::new(UserRole::Admin)))
+        .route("/.well-known/jwks.json", web::get().to(get_jwks))
+        .route("/auth/refresh", web::post().to(refresh_token))
+        .route("/auth/revoke", web::post().to(revoke_token))
+        .route("/shorten", web::post().to(create_short_url).wrap(create_limit()).wrap(member()))
+        .route("/shorten/bulk", web::post().to(create_bulk_short_urls).wrap(create_limit()).wrap(member()))
+        .service(
+            web::scope("/links")
+                .route("", web::get().to(list_short_urls).wrap(read_only()))
+                .route("/{code}", web::get().to(get_short_url).wrap(read_only()))
+                .route("/{code}", web::patch().to(update_short_url).wrap(member()))
+                .route("/{code}", web::delete().to(delete_short_url).wrap(member()))
+                .route("/{code}/stats", web::get().to(get_short_url_stats).wrap(read_only())),
+        )
+        .service(
+            web::scope("/api-keys")
+                .route("", web::get().to(list_api_keys).wrap(read_only()))
+                .route("", web::post().to(create_api_key).wrap(member()))
+                .route("/{id}", web::delete().to(revoke_api_key).wrap(member())),
+        )
+        .route("/organization", web::get().to(get_own_organization).wrap(read_only()))
+        .route("/me/usage", web::get().to(get_own_usage).wrap(read_only()))
+        .service(
+            web::scope("/admin")
+                .wrap(RequireAuth::new(UserRole::Admin))
+                .route("/domain-rules", web::get().to(list_domain_rules))
+                .route("/domain-rules", web::post().to(create_domain_rule))
+                .route("/domain-rules/{id}", web::delete().to(remove_domain_rule))
+                .route("/organizations", web::get().to(list_organization_accounts))
+                .route("/organizations", web::post().to(create_organization_account))
+                .route("/organizations/{id}", web::get().to(get_organization_account))
+                .route("/organizations/{id}", web::patch().to(update_organization_account))
+                .route("/organizations/{id}", web::delete().to(delete_organization_account))
+                .route("/organizations/{id}/quota", web::get().to(get_organization_link_quota))
+                .route("/organizations/{id}/quota", web::put().to(set_organization_link_quota))
+                .route("/users", web::get().to(list_user_accounts))
+                .route("/users", web::post().to(create_user_account))
+                .route("/users/{id}", web::get().to(get_user_account))
+                .route("/users/{id}", web::patch().to(update_user_account))
+                .route("/users/{id}", web::delete().to(delete_user_account))
+                .route("/users/{id}/deactivate", web::post().to(deactivate_user_account))
+                .route("/users/{id}/revoke-tokens", web::post().to(revoke_user_account_tokens))
+                .route("/users/{id}/quota", web::get().to(get_user_link_quota))
+                .route("/users/{id}/quota", web::put().to(set_user_link_quota))
+                .route("/users/{id}/links", web::get().to(admin_list_user_short_urls))
+                .route("/links", web::get().to(admin_list_short_urls))
+                .route("/links/{code}", web::get().to(admin_get_short_url))
+                .route("/links/{code}", web::patch().to(admin_update_short_url))
+                .route("/links/{code}", web::delete().to(admin_delete_short_url))
+                .route("/stats", web::get().to(get_global_stats)),
+        )
+        .service(SwaggerUi::new("/docs/{_:.*}").url("/api-docs/openapi.json", openapi.clone()))
+        .route(
+            "/{short_url}",
+            web::get().to(redirect_short_url).wrap(RateLimit::new(RateLimitScope::Redirect)),
+        );
 }
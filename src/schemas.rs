@@ -3,21 +3,22 @@ use secrecy::{ ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use sqlx::ConnectOptions;
-use utoipa:: ToSchema;
+use utoipa::{IntoParams, ToSchema};
 use sqlx::postgres::PgConnectOptions;
 use uuid::Uuid;
 
 use crate::errors::GenericError;
 use actix_web::{ web, FromRequest, HttpRequest};
 
-use actix_http::Payload;
+use actix_http::{Payload, StatusCode};
 
-#[allow(dead_code)]
 #[derive(Deserialize, Debug, Serialize, ToSchema)]
 pub struct CreateUrlRequest {
     pub original_url: String,
     pub expiry_date: Option<DateTime<Utc>>,
-    
+    pub alias: Option<String>,
+    pub strategy: Option<ShortUrlStrategy>,
+    pub redirect_type: Option<RedirectType>,
 }
 
 impl FromRequest for CreateUrlRequest {
@@ -40,7 +41,556 @@ impl FromRequest for CreateUrlRequest {
 #[derive(Debug, Serialize, ToSchema)]
 pub struct CreateUrlResponseData {
     pub short_url: String,
+    pub expiry_date: Option<DateTime<Utc>>,
+    pub redirect_type: RedirectType,
+
+}
+
+
+#[derive(Deserialize, Debug, Serialize, Clone, Copy, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkCreateMode {
+    #[default]
+    AllOrNothing,
+    BestEffort,
+}
+
+
+#[derive(Deserialize, Debug, Serialize, ToSchema)]
+pub struct BulkCreateUrlRequest {
+    pub urls: Vec<CreateUrlRequest>,
+    #[serde(default)]
+    pub mode: BulkCreateMode,
+}
+
+impl FromRequest for BulkCreateUrlRequest {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Json::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(json) => Ok(json.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BulkCreateUrlResult {
+    pub index: usize,
+    pub status: bool,
+    pub code: String,
+    pub short_url: Option<String>,
+    pub expiry_date: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BulkCreateUrlResponseData {
+    pub created: usize,
+    pub failed: usize,
+    pub results: Vec<BulkCreateUrlResult>,
+}
+
+
+#[derive(Deserialize, Debug, Serialize, ToSchema)]
+pub struct UpdateUrlRequest {
+    pub original_url: Option<String>,
+    /// Left alone when missing, `null` removes the expiry.
+    #[serde(default, with = "serde_with::rust::double_option")]
+    #[schema(value_type = Option<DateTime<Utc>>, nullable)]
+    pub expiry_date: Option<Option<DateTime<Utc>>>,
+    pub redirect_type: Option<RedirectType>,
+}
+
+impl FromRequest for UpdateUrlRequest {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Json::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(json) => Ok(json.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct ListUrlQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub search: Option<String>,
+    pub created_by: Option<Uuid>,
+}
+
+impl FromRequest for ListUrlQuery {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Query::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(query) => Ok(query.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UrlData {
+    pub code: String,
+    pub short_url: String,
+    pub original_url: String,
+    pub created_on: DateTime<Utc>,
+    pub expiry_date: Option<DateTime<Utc>>,
+    pub redirect_type: Option<RedirectType>,
+    pub created_by: Uuid,
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UrlListData {
+    pub urls: Vec<UrlData>,
+    pub total: i64,
+    pub page: i64,
+    pub limit: i64,
+}
+
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StatsInterval {
+    Hourly,
+    #[default]
+    Daily,
+    Weekly,
+}
+
+impl StatsInterval {
+    pub fn date_trunc_unit(&self) -> &'static str {
+        match self {
+            StatsInterval::Hourly => "hour",
+            StatsInterval::Daily => "day",
+            StatsInterval::Weekly => "week",
+        }
+    }
+}
+
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct UrlStatsQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub interval: Option<StatsInterval>,
+}
+
+impl FromRequest for UrlStatsQuery {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Query::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(query) => Ok(query.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ClickBucket {
+    pub bucket: DateTime<Utc>,
+    pub clicks: i64,
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StatCount {
+    pub value: String,
+    pub clicks: i64,
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UrlStatsData {
+    pub code: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub interval: StatsInterval,
+    pub total_clicks: i64,
+    pub unique_visitors: i64,
+    pub clicks_over_time: Vec<ClickBucket>,
+    pub top_referrers: Vec<StatCount>,
+    pub top_user_agents: Vec<StatCount>,
+    pub top_browsers: Vec<StatCount>,
+    pub top_os: Vec<StatCount>,
+    pub languages: Vec<StatCount>,
+}
+
+
+#[derive(Deserialize, Debug, Serialize, ToSchema)]
+pub struct CreateDomainRuleRequest {
+    pub pattern: String,
+    pub rule_type: DomainRuleType,
+}
+
+impl FromRequest for CreateDomainRuleRequest {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Json::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(json) => Ok(json.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DomainRuleData {
+    pub id: i32,
+    pub pattern: String,
+    pub rule_type: DomainRuleType,
+    pub created_on: DateTime<Utc>,
+    pub created_by: Uuid,
+}
+
+
+#[derive(Deserialize, Debug, Serialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub expiry_date: Option<DateTime<Utc>>,
+}
+
+impl FromRequest for CreateApiKeyRequest {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Json::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(json) => Ok(json.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiKeyData {
+    pub id: Uuid,
+    pub name: String,
+    pub key_prefix: String,
+    pub created_on: DateTime<Utc>,
+    pub last_used_on: Option<DateTime<Utc>>,
+    pub expiry_date: Option<DateTime<Utc>>,
+    pub revoked_on: Option<DateTime<Utc>>,
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreateApiKeyResponseData {
+    /// The full key, it is only returned once and cannot be recovered afterwards.
+    pub key: String,
+    pub api_key: ApiKeyData,
+}
+
+
+#[derive(Deserialize, Debug, Serialize, ToSchema)]
+pub struct CreateUserRequest {
+    pub username: String,
+    pub organization_id: Uuid,
+    #[serde(default)]
+    pub role: UserRole,
+}
+
+impl FromRequest for CreateUserRequest {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Json::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(json) => Ok(json.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Deserialize, Debug, Serialize, ToSchema)]
+pub struct UpdateUserRequest {
+    pub username: Option<String>,
+    pub organization_id: Option<Uuid>,
+    pub is_active: Option<bool>,
+    pub role: Option<UserRole>,
+}
+
+impl FromRequest for UpdateUserRequest {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Json::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(json) => Ok(json.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct ListUserQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+impl FromRequest for ListUserQuery {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Query::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(query) => Ok(query.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserData {
+    pub id: Uuid,
+    pub username: String,
+    pub organization_id: Uuid,
+    pub role: UserRole,
+    pub is_active: bool,
+    pub created_on: DateTime<Utc>,
+    pub deactivated_on: Option<DateTime<Utc>>,
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UserListData {
+    pub users: Vec<UserData>,
+    pub total: i64,
+    pub page: i64,
+    pub limit: i64,
+}
+
+
+#[derive(Deserialize, Debug, Serialize, ToSchema)]
+pub struct CreateOrganizationRequest {
+    pub name: String,
+    /// Expiry applied to new links that do not set one.
+    pub default_expiry_days: Option<i32>,
+    /// Destination domains members may link to, any domain when empty.
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    /// Length of generated codes, the application default when unset.
+    pub short_url_length: Option<i32>,
+}
+
+impl FromRequest for CreateOrganizationRequest {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Json::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(json) => Ok(json.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
 
+/// Fields left out are unchanged, `0` clears `default_expiry_days` and `short_url_length`.
+#[derive(Deserialize, Debug, Serialize, ToSchema)]
+pub struct UpdateOrganizationRequest {
+    pub name: Option<String>,
+    pub default_expiry_days: Option<i32>,
+    pub allowed_domains: Option<Vec<String>>,
+    pub short_url_length: Option<i32>,
+}
+
+impl FromRequest for UpdateOrganizationRequest {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Json::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(json) => Ok(json.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OrganizationData {
+    pub id: Uuid,
+    pub name: String,
+    pub default_expiry_days: Option<i32>,
+    pub allowed_domains: Vec<String>,
+    pub short_url_length: Option<i32>,
+    pub created_on: DateTime<Utc>,
+}
+
+
+/// Replaces the quota of a user or organization. A missing or null limit means unlimited.
+#[derive(Deserialize, Debug, Serialize, Default, ToSchema)]
+pub struct SetLinkQuotaRequest {
+    pub max_active_links: Option<i32>,
+    pub max_links_per_day: Option<i32>,
+    pub max_links_per_month: Option<i32>,
+}
+
+impl FromRequest for SetLinkQuotaRequest {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Json::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(json) => Ok(json.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, Default, Clone, Copy, PartialEq, ToSchema)]
+pub struct LinkQuotaData {
+    pub max_active_links: Option<i32>,
+    pub max_links_per_day: Option<i32>,
+    pub max_links_per_month: Option<i32>,
+}
+
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, ToSchema)]
+pub struct QuotaUsageData {
+    pub used: i64,
+    pub limit: Option<i32>,
+}
+
+
+/// Days and months are calendar days and months in UTC.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LinkUsageScopeData {
+    pub active_links: QuotaUsageData,
+    pub links_today: QuotaUsageData,
+    pub links_this_month: QuotaUsageData,
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LinkUsageData {
+    pub user: LinkUsageScopeData,
+    pub organization: LinkUsageScopeData,
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DatabaseHealthData {
+    pub ok: bool,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+
+/// Connections of the pool at the time of the check, before the check took one itself.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PoolHealthData {
+    pub size: u32,
+    pub idle: u32,
+    pub in_use: u32,
+    pub max_connections: u32,
+    /// `in_use / max_connections`
+    pub saturation: f64,
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MigrationHealthData {
+    pub ok: bool,
+    pub expected_version: i32,
+    pub applied_version: Option<i32>,
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReadinessData {
+    pub ready: bool,
+    pub database: DatabaseHealthData,
+    pub pool: PoolHealthData,
+    pub migrations: MigrationHealthData,
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GlobalStatsData {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub interval: StatsInterval,
+    pub total_users: i64,
+    pub active_users: i64,
+    pub total_links: i64,
+    pub active_links: i64,
+    pub total_clicks: i64,
+    pub unique_visitors: i64,
+    pub clicks_over_time: Vec<ClickBucket>,
+    pub top_links: Vec<StatCount>,
 }
 
 
@@ -48,6 +598,53 @@ pub struct CreateUrlResponseData {
 pub struct JWTClaims {
     pub sub: Uuid,
     pub exp: usize,
+    pub role: UserRole,
+    /// `user_account.token_version` at issue time, bumping it revokes every token issued before.
+    /// Required, tokens issued before it existed carry the old ~100 year expiry and are rejected.
+    pub ver: i32,
+}
+
+
+#[derive(Deserialize, Debug, Serialize, ToSchema)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+impl FromRequest for RefreshTokenRequest {
+    type Error = GenericError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let fut = web::Json::<Self>::from_request(req, payload);
+
+        Box::pin(async move {
+            match fut.await {
+                Ok(json) => Ok(json.into_inner()),
+                Err(e) => Err(GenericError::ValidationError(e.to_string())),
+            }
+        })
+    }
+}
+
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TokenPairData {
+    pub access_token: String,
+    pub token_type: String,
+    /// Lifetime of the access token in seconds.
+    pub expires_in: i64,
+    /// Single use, every refresh returns a new one and invalidates the old one.
+    pub refresh_token: String,
+    pub refresh_token_expiry_date: DateTime<Utc>,
+}
+
+
+/// Identity the auth middleware attaches to every authenticated request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuthenticatedUser {
+    pub id: Uuid,
+    pub organization_id: Uuid,
+    pub role: UserRole,
 }
 
 
@@ -122,15 +719,125 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub workers: usize,
-    pub domain: String
+    pub domain: String,
+    #[serde(default = "default_short_url_length")]
+    pub short_url_length: usize,
+    #[serde(default = "default_short_url_retries")]
+    pub short_url_retries: u32,
+    #[serde(default)]
+    pub short_url_strategy: ShortUrlStrategy,
+    /// Secret shuffle of the sqids alphabet. Without it the sequential strategy is disabled,
+    /// codes made with a known alphabet decode to row ids and let anyone enumerate all links.
+    pub sequential_alphabet: Option<String>,
+    #[serde(default = "default_bulk_create_limit")]
+    pub bulk_create_limit: usize,
+    #[serde(default = "default_redirect_cache_capacity")]
+    pub redirect_cache_capacity: u64,
+    #[serde(default = "default_redirect_cache_ttl_secs")]
+    pub redirect_cache_ttl_secs: u64,
+    #[serde(default = "default_redirect_cache_negative_ttl_secs")]
+    pub redirect_cache_negative_ttl_secs: u64,
+    #[serde(default)]
+    pub default_redirect_type: RedirectType,
+    #[serde(default = "default_permanent_redirect_max_age_secs")]
+    pub permanent_redirect_max_age_secs: u64,
+    #[serde(default = "default_allowed_url_schemes")]
+    pub allowed_url_schemes: Vec<String>,
+    #[serde(default = "default_domain_rules_refresh_secs")]
+    pub domain_rules_refresh_secs: u64,
+    #[serde(default = "default_shortener_domains")]
+    pub shortener_domains: Vec<String>,
+    #[serde(default)]
+    pub shortener_policy: ShortenerPolicy,
+    #[serde(default = "default_shortener_max_depth")]
+    pub shortener_max_depth: usize,
+    #[serde(default = "default_shortener_resolve_timeout_ms")]
+    pub shortener_resolve_timeout_ms: u64,
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// Reverse proxies whose `X-Forwarded-For` is believed, see `client_ip`.
+    #[serde(default)]
+    pub trusted_proxies: Vec<std::net::IpAddr>,
+}
+
+fn default_short_url_length() -> usize {
+    6
+}
+
+fn default_short_url_retries() -> u32 {
+    5
+}
+
+fn default_bulk_create_limit() -> usize {
+    1000
 }
 
+fn default_redirect_cache_capacity() -> u64 {
+    100_000
+}
+
+fn default_redirect_cache_ttl_secs() -> u64 {
+    300
+}
+
+fn default_redirect_cache_negative_ttl_secs() -> u64 {
+    30
+}
+
+fn default_permanent_redirect_max_age_secs() -> u64 {
+    86400
+}
+
+fn default_allowed_url_schemes() -> Vec<String> {
+    vec!["http".to_string(), "https".to_string()]
+}
+
+fn default_domain_rules_refresh_secs() -> u64 {
+    60
+}
+
+fn default_shortener_domains() -> Vec<String> {
+    ["bit.ly", "tinyurl.com", "t.co", "goo.gl", "ow.ly", "is.gd", "buff.ly", "rebrand.ly", "cutt.ly"]
+        .iter()
+        .map(|domain| domain.to_string())
+        .collect()
+}
+
+fn default_shortener_max_depth() -> usize {
+    5
+}
+
+fn default_shortener_resolve_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+
 
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Jwt {
-    pub secret: SecretString,
-    pub expiry: i64,
+    /// HS256 secret. Once `key_dir` is set it only keeps verifying tokens signed before the switch.
+    pub secret: Option<SecretString>,
+    /// Directory of `<kid>.pub.pem` public keys (RSA, P-256 or Ed25519) accepted for verification.
+    pub key_dir: Option<String>,
+    /// Key that signs new tokens, read from `<key_dir>/<kid>.pem`.
+    pub signing_key_id: Option<String>,
+    #[serde(default = "default_access_token_expiry_secs")]
+    pub access_token_expiry_secs: i64,
+    #[serde(default = "default_refresh_token_expiry_days")]
+    pub refresh_token_expiry_days: i64,
+}
+
+fn default_access_token_expiry_secs() -> i64 {
+    900
+}
+
+fn default_refresh_token_expiry_days() -> i64 {
+    30
 }
 
 
@@ -138,6 +845,95 @@ pub struct Jwt {
 #[derive(Debug, Deserialize, Clone)]
 pub struct SecretSetting {
     pub jwt: Jwt,
+    /// Keys the visitor IP hashes. When unset a random salt is used, so unique visitors are
+    /// only counted per process and not across restarts or instances.
+    pub ip_hash_salt: Option<SecretString>,
+}
+
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct AnalyticsSettings {
+    pub buffer_size: usize,
+    pub batch_size: usize,
+    pub flush_interval_ms: u64,
+}
+
+impl Default for AnalyticsSettings {
+    fn default() -> Self {
+        Self {
+            buffer_size: 10000,
+            batch_size: 500,
+            flush_interval_ms: 1000,
+        }
+    }
+}
+
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitBackend {
+    /// Buckets live in each instance, limits apply per instance.
+    #[default]
+    Memory,
+    /// Buckets live in the `rate_limit_bucket` table and are shared by all instances.
+    Postgres,
+}
+
+/// Token buckets refilled at `*_per_minute` that hold up to `*_burst` requests. A rate of 0 disables the limit.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    pub backend: RateLimitBackend,
+    /// Upper bound on the buckets the memory backend keeps, the least used are evicted beyond it.
+    pub memory_max_buckets: u64,
+    pub create_per_minute: u32,
+    pub create_burst: u32,
+    pub redirect_per_minute: u32,
+    pub redirect_burst: u32,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            backend: RateLimitBackend::Memory,
+            memory_max_buckets: 100_000,
+            create_per_minute: 60,
+            create_burst: 20,
+            redirect_per_minute: 600,
+            redirect_burst: 100,
+        }
+    }
+}
+
+
+/// External issuer whose JWTs are accepted next to our own, see `IdentityProvider`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct IdentityProviderSettings {
+    pub issuer: String,
+    pub audience: String,
+    /// Exactly one of `jwks_url` and `jwks_file` must be set.
+    pub jwks_url: Option<String>,
+    pub jwks_file: Option<String>,
+    #[serde(default = "default_jwks_cache_secs")]
+    pub jwks_cache_secs: u64,
+    /// Claim holding the username of the matching `user_account`.
+    #[serde(default = "default_user_claim")]
+    pub user_claim: String,
+    #[serde(default)]
+    pub auto_provision: bool,
+    /// Organization that accounts created on first login are added to, required with `auto_provision`.
+    pub provision_organization: Option<String>,
+    #[serde(default)]
+    pub provision_role: UserRole,
+}
+
+fn default_jwks_cache_secs() -> u64 {
+    300
+}
+
+fn default_user_claim() -> String {
+    "sub".to_string()
 }
 
 
@@ -146,6 +942,11 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub secret: SecretSetting,
+    #[serde(default)]
+    pub analytics: AnalyticsSettings,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    pub identity_provider: Option<IdentityProviderSettings>,
 }
 
 
@@ -157,4 +958,102 @@ pub enum DataSource {
     PlaceOrder,
     TradeIndia,
     Rapidor,
-}
\ No newline at end of file
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ShortUrlStrategy {
+    #[default]
+    Random,
+    Sequential,
+    HumanFriendly,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "redirect_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectType {
+    MovedPermanently,
+    #[default]
+    Found,
+    TemporaryRedirect,
+    PermanentRedirect,
+}
+
+impl RedirectType {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            RedirectType::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+            RedirectType::Found => StatusCode::FOUND,
+            RedirectType::TemporaryRedirect => StatusCode::TEMPORARY_REDIRECT,
+            RedirectType::PermanentRedirect => StatusCode::PERMANENT_REDIRECT,
+        }
+    }
+
+    /// Permanent redirects may be cached by browsers and crawlers, temporary ones must be
+    /// revalidated so destination changes and click tracking take effect immediately.
+    pub fn cache_control(&self, permanent_max_age_secs: u64) -> String {
+        match self {
+            RedirectType::MovedPermanently | RedirectType::PermanentRedirect => {
+                format!("public, max-age={}", permanent_max_age_secs)
+            }
+            RedirectType::Found | RedirectType::TemporaryRedirect => "private, no-cache".to_string(),
+        }
+    }
+}
+
+
+/// What to do with destinations that are themselves short links.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShortenerPolicy {
+    #[default]
+    Reject,
+    Resolve,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "domain_rule_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DomainRuleType {
+    Block,
+    Allow,
+}
+
+
+/// Declared from least to most privileged, the derived ordering is what role checks compare.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "user_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum UserRole {
+    ReadOnly,
+    #[default]
+    Member,
+    Admin,
+}
+
+impl std::fmt::Display for UserRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserRole::ReadOnly => write!(f, "read_only"),
+            UserRole::Member => write!(f, "member"),
+            UserRole::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl std::str::FromStr for UserRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read_only" => Ok(UserRole::ReadOnly),
+            "member" => Ok(UserRole::Member),
+            "admin" => Ok(UserRole::Admin),
+            _ => Err(anyhow::anyhow!("Unknown role {}, expected read_only, member or admin", s)),
+        }
+    }
+}
@@ -2,13 +2,44 @@
 use actix_web::dev::Server;
 use actix_web::{web, App, HttpServer};
 use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::future::Future;
 use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinHandle;
 use tracing_actix_web::TracingLogger;
+use crate::analytics::{resolve_ip_hash_salt, ClickRecorder};
+use crate::cache::RedirectCache;
+use crate::destinations::DestinationResolver;
+use crate::domain_rules::DomainPolicy;
+use crate::generators::ShortUrlGenerators;
+use crate::rate_limit::RateLimiter;
+use crate::identity_provider::IdentityProvider;
+use crate::jwt_keys::JwtKeys;
+use crate::metrics::Metrics;
+use crate::quotas::spawn_link_creation_cleanup;
+use crate::middlewares::RecordMetrics;
 use crate::schemas::{DatabaseSettings, Settings};
 use crate::routes::routes;
+use crate::telemetry::AppRootSpanBuilder;
+const RATE_LIMIT_CLEANUP_INTERVAL_SECS: u64 = 300;
+const LINK_CREATION_CLEANUP_INTERVAL_SECS: u64 = 3600;
+
 pub struct Application {
     port: u16,
     server: Server,
+    pool: PgPool,
+    shutdown_timeout: Duration,
+    background_tasks: BackgroundTasks,
+}
+
+/// Tasks spawned next to the server that have to be stopped once it is done.
+struct BackgroundTasks {
+    /// Ends on its own after a final flush, once the server has dropped every `ClickRecorder`.
+    click_writer: JoinHandle<()>,
+    /// Loop forever and are aborted.
+    periodic: Vec<JoinHandle<()>>,
 }
 impl Application {
     // We have converted the `build` function into a constructor for
@@ -22,19 +53,56 @@ impl Application {
         println!("Listening {}", address);
         let listener = TcpListener::bind(&address)?;
         let port = listener.local_addr().unwrap().port();
-        let server = run(
+        let shutdown_timeout = Duration::from_secs(configuration.application.shutdown_timeout_secs);
+        let (server, background_tasks) = run(
             listener,
-            connection_pool,
+            connection_pool.clone(),
             configuration,
         )
         .await?;
-        Ok(Self { port, server })
+        Ok(Self { port, server, pool: connection_pool, shutdown_timeout, background_tasks })
     }
     pub fn port(&self) -> u16 {
         self.port
     }
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        self.run_until(shutdown_signal()).await
+    }
+
+    /// Serves until `shutdown` resolves, then stops accepting connections, gives in-flight requests
+    /// up to `shutdown_timeout` to finish, writes the buffered clicks and closes the pool.
+    pub async fn run_until(self, shutdown: impl Future<Output = ()>) -> Result<(), std::io::Error> {
+        let mut server = self.server;
+        let handle = server.handle();
+        let result = tokio::select! {
+            result = &mut server => result,
+            _ = shutdown => {
+                tracing::info!(timeout = ?self.shutdown_timeout, "Shutting down, draining in-flight requests");
+                // The server future carries out the stop command, so both have to be polled.
+                let ((), result) = tokio::join!(handle.stop(true), server);
+                result
+            }
+        };
+
+        for task in &self.background_tasks.periodic {
+            task.abort();
+        }
+        match tokio::time::timeout(self.shutdown_timeout, self.background_tasks.click_writer).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => tracing::error!("Click writer failed: {:?}", e),
+            Err(_) => tracing::error!("Click writer did not finish within {:?}, clicks were lost", self.shutdown_timeout),
+        }
+        self.pool.close().await;
+        tracing::info!("Shutdown complete");
+        result
+    }
+}
+
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => tracing::info!("Received SIGINT"),
+        _ = terminate.recv() => tracing::info!("Received SIGTERM"),
     }
 }
 
@@ -51,25 +119,73 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 async fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    configuration: Settings,
-) -> Result<Server, anyhow::Error> {
+    mut configuration: Settings,
+) -> Result<(Server, BackgroundTasks), anyhow::Error> {
+    configuration.secret.ip_hash_salt = Some(resolve_ip_hash_salt(configuration.secret.ip_hash_salt.take()));
+    let (click_recorder, click_writer) = ClickRecorder::spawn(db_pool.clone(), &configuration.analytics);
+    let mut periodic = vec![];
+    let click_recorder_obj = web::Data::new(click_recorder);
     let db_pool = web::Data::new(db_pool);
+    let jwt_keys_obj = web::Data::new(JwtKeys::from_settings(&configuration.secret.jwt)?);
+    let identity_provider_obj = configuration
+        .identity_provider
+        .as_ref()
+        .map(IdentityProvider::new)
+        .transpose()?
+        .map(web::Data::new);
     let secret_obj = web::Data::new(configuration.secret);
     let workers = configuration.application.workers;
+    let shutdown_timeout_secs = configuration.application.shutdown_timeout_secs;
+    let generators_obj = web::Data::new(ShortUrlGenerators::new(&configuration.application)?);
+    let redirect_cache = RedirectCache::new(&configuration.application);
+    let metrics_obj = web::Data::new(Metrics::new(&redirect_cache)?);
+    let redirect_cache_obj = web::Data::new(redirect_cache);
+    let domain_policy = Arc::new(DomainPolicy::default());
+    domain_policy.reload(&db_pool).await?;
+    periodic.push(domain_policy.clone().spawn_refresh(
+        db_pool.get_ref().clone(),
+        Duration::from_secs(configuration.application.domain_rules_refresh_secs),
+    ));
+    let domain_policy_obj = web::Data::from(domain_policy);
+    let rate_limiter = Arc::new(RateLimiter::new(&configuration.rate_limit, db_pool.get_ref().clone()));
+    periodic.extend(rate_limiter.clone().spawn_cleanup(Duration::from_secs(RATE_LIMIT_CLEANUP_INTERVAL_SECS)));
+    periodic.push(spawn_link_creation_cleanup(
+        db_pool.get_ref().clone(),
+        Duration::from_secs(LINK_CREATION_CLEANUP_INTERVAL_SECS),
+    ));
+    let rate_limiter_obj = web::Data::from(rate_limiter);
+    let resolver_obj = web::Data::new(DestinationResolver::new(&configuration.application)?);
     let application_obj = web::Data::new(configuration.application);
     // let _secret_key = Key::from(hmac_secret.expose_secret().as_bytes())
     let server = HttpServer::new(move || {
         App::new()
             //.app_data(web::JsonConfig::default().limit(1024 * 1024 * 50))
-            .wrap(TracingLogger::default())
+            .wrap(RecordMetrics)
+            .wrap(TracingLogger::<AppRootSpanBuilder>::new())
             .app_data(db_pool.clone())
             .app_data(secret_obj.clone())
+            .app_data(jwt_keys_obj.clone())
             .app_data(application_obj.clone())
+            .app_data(generators_obj.clone())
+            .app_data(click_recorder_obj.clone())
+            .app_data(redirect_cache_obj.clone())
+            .app_data(domain_policy_obj.clone())
+            .app_data(resolver_obj.clone())
+            .app_data(rate_limiter_obj.clone())
+            .app_data(metrics_obj.clone())
+            .configure(|cfg| {
+                if let Some(identity_provider) = &identity_provider_obj {
+                    cfg.app_data(identity_provider.clone());
+                }
+            })
             .configure(routes)
     })
     .workers(workers)
+    // SIGINT would stop actix without draining, `Application::run_until_stopped` handles both signals instead.
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout_secs)
     .listen(listener)?
     .run();
 
-    Ok(server)
+    Ok((server, BackgroundTasks { click_writer, periodic }))
 }
@@ -6,6 +6,32 @@ use tracing_subscriber::{
     filter::LevelFilter, fmt, layer::SubscriberExt, EnvFilter, Layer, Registry,
 };
 use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::trace::TracerProvider;
+use std::time::Duration;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use tracing::Span;
+use tracing_actix_web::{DefaultRootSpanBuilder, RootSpanBuilder};
+
+const UNTRACED_PATHS: [&str; 3] = ["/health/live", "/health/ready", "/metrics"];
+
+/// `DefaultRootSpanBuilder` without spans for the health probes and metric scrapes, which
+/// would otherwise make up most of the logged and exported requests.
+pub struct AppRootSpanBuilder;
+
+impl RootSpanBuilder for AppRootSpanBuilder {
+    fn on_request_start(request: &ServiceRequest) -> Span {
+        if UNTRACED_PATHS.contains(&request.path()) {
+            return Span::none();
+        }
+        DefaultRootSpanBuilder::on_request_start(request)
+    }
+
+    fn on_request_end<B: MessageBody>(span: Span, outcome: &Result<ServiceResponse<B>, actix_web::Error>) {
+        DefaultRootSpanBuilder::on_request_end(span, outcome);
+    }
+}
+
 pub fn get_subscriber<Sink>(
     _name: String,
     env_filter: String,
@@ -30,20 +56,22 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
 
 
 
+/// The returned provider has to be passed to `shutdown_tracer_provider` on exit, spans still
+/// waiting in the batch exporter are lost otherwise.
 pub fn get_subscriber_with_jeager<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
-) -> impl Subscriber + Send + Sync
+) -> (impl Subscriber + Send + Sync, TracerProvider)
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
-    let tracer: opentelemetry_sdk::trace::Tracer = opentelemetry_otlp::new_pipeline()
+    let tracer_provider = opentelemetry_otlp::new_pipeline()
         .tracing()
         .with_exporter(opentelemetry_otlp::new_exporter().tonic())
         .install_batch(opentelemetry_sdk::runtime::Tokio)
-    
-        .expect("Couldn't create OTLP tracer").tracer(name);
+        .expect("Couldn't create OTLP tracer");
+    let tracer: opentelemetry_sdk::trace::Tracer = tracer_provider.tracer(name);
     let telemetry_layer: tracing_opentelemetry::OpenTelemetryLayer<
         Registry,
         opentelemetry_sdk::trace::Tracer,
@@ -54,8 +82,21 @@ where
         .with_ansi(true)
         .with_writer(sink)
         .with_filter(LevelFilter::INFO);
-    Registry::default()
+    let subscriber = Registry::default()
         .with(telemetry_layer)
         .with(env_filter)
-        .with(format_layer)
+        .with(format_layer);
+    (subscriber, tracer_provider)
+}
+
+/// Exports the remaining spans. The batch processor runs on this runtime while `shutdown` blocks
+/// until it is done, so the blocking part has to happen on another thread.
+pub async fn shutdown_tracer_provider(tracer_provider: TracerProvider, timeout: Duration) {
+    let shutdown = tokio::task::spawn_blocking(move || tracer_provider.shutdown());
+    match tokio::time::timeout(timeout, shutdown).await {
+        Ok(Ok(Ok(()))) => {}
+        Ok(Ok(Err(e))) => eprintln!("Failed to shut down the tracer provider: {}", e),
+        Ok(Err(e)) => eprintln!("Failed to shut down the tracer provider: {}", e),
+        Err(_) => eprintln!("Tracer provider did not shut down within {:?}, spans were lost", timeout),
+    }
 }
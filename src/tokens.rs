@@ -0,0 +1,216 @@
+use chrono::{DateTime, Duration, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::errors::GenericError;
+use crate::jwt_keys::JwtKeys;
+use crate::schemas::{Jwt, TokenPairData, UserRole};
+use crate::utils::generate_jwt_token_for_user;
+
+const REFRESH_TOKEN_PREFIX: &str = "rt_";
+const REFRESH_TOKEN_LENGTH: usize = 48;
+
+/// What an access token is issued for, read from `user_account` when a token pair is created.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenSubject {
+    pub user_id: Uuid,
+    pub role: UserRole,
+    pub token_version: i32,
+}
+
+pub fn generate_refresh_token() -> String {
+    let secret: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(REFRESH_TOKEN_LENGTH)
+        .map(char::from)
+        .collect();
+    format!("{}{}", REFRESH_TOKEN_PREFIX, secret)
+}
+
+/// Refresh tokens are long random strings, only their SHA-256 is stored.
+pub fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub fn build_token_pair(
+    subject: &TokenSubject,
+    refresh_token: String,
+    refresh_token_expiry_date: DateTime<Utc>,
+    jwt: &Jwt,
+    keys: &JwtKeys,
+) -> Result<TokenPairData, anyhow::Error> {
+    let access_token = generate_jwt_token_for_user(
+        subject.user_id,
+        subject.role,
+        subject.token_version,
+        jwt.access_token_expiry_secs,
+        keys,
+    )?;
+    Ok(TokenPairData {
+        access_token: access_token.expose_secret().to_string(),
+        token_type: "Bearer".to_string(),
+        expires_in: jwt.access_token_expiry_secs,
+        refresh_token,
+        refresh_token_expiry_date,
+    })
+}
+
+async fn fetch_token_subject(
+    connection: &mut PgConnection,
+    user_id: &Uuid,
+) -> Result<Option<TokenSubject>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT id, role AS "role: UserRole", token_version FROM user_account WHERE id = $1 AND is_active"#,
+        user_id
+    )
+    .fetch_optional(connection)
+    .await?;
+    Ok(row.map(|row| TokenSubject {
+        user_id: row.id,
+        role: row.role,
+        token_version: row.token_version,
+    }))
+}
+
+async fn insert_refresh_token(
+    connection: &mut PgConnection,
+    user_id: &Uuid,
+    family_id: &Uuid,
+    expiry_days: i64,
+) -> Result<(String, DateTime<Utc>), anyhow::Error> {
+    let token = generate_refresh_token();
+    let created_on = Utc::now();
+    let expiry_date = created_on + Duration::days(expiry_days);
+    sqlx::query!(
+        r#"INSERT INTO refresh_token (id, user_id, family_id, token_hash, created_on, expiry_date)
+        VALUES ($1, $2, $3, $4, $5, $6)"#,
+        Uuid::new_v4(),
+        user_id,
+        family_id,
+        hash_refresh_token(&token),
+        created_on,
+        expiry_date
+    )
+    .execute(connection)
+    .await?;
+    Ok((token, expiry_date))
+}
+
+/// Starts a new refresh token family for an active user. `None` when the user is missing or inactive.
+#[tracing::instrument(name = "issue_token_pair", skip(pool, jwt, keys))]
+pub async fn issue_token_pair(
+    pool: &PgPool,
+    user_id: &Uuid,
+    jwt: &Jwt,
+    keys: &JwtKeys,
+) -> Result<Option<TokenPairData>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let Some(subject) = fetch_token_subject(&mut transaction, user_id).await? else {
+        return Ok(None);
+    };
+    let (refresh_token, expiry_date) =
+        insert_refresh_token(&mut transaction, user_id, &Uuid::new_v4(), jwt.refresh_token_expiry_days).await?;
+    transaction.commit().await?;
+    Ok(Some(build_token_pair(&subject, refresh_token, expiry_date, jwt, keys)?))
+}
+
+/// Exchanges a refresh token for a new pair. A token that was already used means it leaked,
+/// so its whole family is revoked and the legitimate holder has to start over as well.
+#[tracing::instrument(name = "rotate_refresh_token", skip_all)]
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    token: &str,
+    jwt: &Jwt,
+    keys: &JwtKeys,
+) -> Result<TokenPairData, GenericError> {
+    let invalid = || GenericError::InvalidJWT("Invalid refresh token".to_string());
+    let mut transaction = pool.begin().await.map_err(anyhow::Error::from)?;
+    let Some(stored) = sqlx::query!(
+        r#"SELECT id, user_id, family_id, expiry_date, used_on, revoked_on
+        FROM refresh_token WHERE token_hash = $1
+        FOR UPDATE"#,
+        hash_refresh_token(token)
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .map_err(anyhow::Error::from)?
+    else {
+        return Err(invalid());
+    };
+
+    if stored.revoked_on.is_some() {
+        return Err(invalid());
+    }
+    if stored.used_on.is_some() {
+        tracing::warn!(user_id = %stored.user_id, "Refresh token reused, revoking its family");
+        sqlx::query!(
+            "UPDATE refresh_token SET revoked_on = $2 WHERE family_id = $1 AND revoked_on IS NULL",
+            stored.family_id,
+            Utc::now()
+        )
+        .execute(&mut *transaction)
+        .await
+        .map_err(anyhow::Error::from)?;
+        transaction.commit().await.map_err(anyhow::Error::from)?;
+        return Err(invalid());
+    }
+    if stored.expiry_date <= Utc::now() {
+        return Err(GenericError::InvalidJWT("Refresh token expired".to_string()));
+    }
+    let Some(subject) = fetch_token_subject(&mut transaction, &stored.user_id).await? else {
+        return Err(GenericError::InvalidJWT("User is inactive or does not exist".to_string()));
+    };
+
+    sqlx::query!("UPDATE refresh_token SET used_on = $2 WHERE id = $1", stored.id, Utc::now())
+        .execute(&mut *transaction)
+        .await
+        .map_err(anyhow::Error::from)?;
+    let (refresh_token, expiry_date) = insert_refresh_token(
+        &mut transaction,
+        &stored.user_id,
+        &stored.family_id,
+        jwt.refresh_token_expiry_days,
+    )
+    .await?;
+    transaction.commit().await.map_err(anyhow::Error::from)?;
+    Ok(build_token_pair(&subject, refresh_token, expiry_date, jwt, keys)?)
+}
+
+/// Revokes the family of the given refresh token, i.e. ends that session. Returns false for unknown tokens.
+#[tracing::instrument(name = "revoke_refresh_token", skip_all)]
+pub async fn revoke_refresh_token(pool: &PgPool, token: &str) -> Result<bool, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"UPDATE refresh_token SET revoked_on = $2
+        WHERE revoked_on IS NULL
+            AND family_id = (SELECT family_id FROM refresh_token WHERE token_hash = $1)"#,
+        hash_refresh_token(token),
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Invalidates every access and refresh token issued to the user so far. Returns false for unknown users.
+#[tracing::instrument(name = "revoke_user_tokens", skip(pool))]
+pub async fn revoke_user_tokens(pool: &PgPool, user_id: &Uuid) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let result = sqlx::query!(
+        "UPDATE user_account SET token_version = token_version + 1 WHERE id = $1",
+        user_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "UPDATE refresh_token SET revoked_on = $2 WHERE user_id = $1 AND revoked_on IS NULL",
+        user_id,
+        Utc::now()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,206 @@
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::errors::GenericError;
+use crate::models::UserAccountModel;
+use crate::schemas::{AuthenticatedUser, UpdateUserRequest, UserRole};
+
+const USERNAME_MAX_LENGTH: usize = 64;
+
+pub fn validate_username(username: &str) -> Result<(), GenericError> {
+    if username.is_empty() || username.len() > USERNAME_MAX_LENGTH {
+        return Err(GenericError::ValidationError(format!(
+            "Username must be between 1 and {} characters long",
+            USERNAME_MAX_LENGTH
+        )));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@'))
+    {
+        return Err(GenericError::ValidationError(
+            "Username may only contain letters, digits, '-', '_', '.' and '@'".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn map_user_write_error(e: sqlx::Error, username: Option<&str>) -> GenericError {
+    match &e {
+        sqlx::Error::Database(db_error) if db_error.is_unique_violation() => GenericError::Conflict(
+            format!("Username '{}' is already taken", username.unwrap_or_default()),
+        ),
+        sqlx::Error::Database(db_error) if db_error.is_foreign_key_violation() => {
+            GenericError::ValidationError("Organization does not exist".to_string())
+        }
+        _ => GenericError::UnexpectedError(e.into()),
+    }
+}
+
+#[tracing::instrument(name = "insert_user", skip(pool))]
+pub async fn insert_user(
+    pool: &PgPool,
+    username: &str,
+    organization_id: &Uuid,
+    role: UserRole,
+) -> Result<UserAccountModel, GenericError> {
+    sqlx::query_as!(
+        UserAccountModel,
+        r#"INSERT INTO user_account (id, username, organization_id, role, is_active, created_on)
+        VALUES ($1, $2, $3, $5, TRUE, $4)
+        RETURNING id, username, organization_id, role AS "role: UserRole", is_active, created_on, deactivated_on"#,
+        Uuid::new_v4(),
+        username,
+        organization_id,
+        Utc::now(),
+        role as UserRole
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| map_user_write_error(e, Some(username)))
+}
+
+#[tracing::instrument(name = "list_users", skip(pool))]
+pub async fn list_users(
+    pool: &PgPool,
+    limit: i64,
+    offset: i64,
+) -> Result<(Vec<UserAccountModel>, i64), anyhow::Error> {
+    let users = sqlx::query_as!(
+        UserAccountModel,
+        r#"SELECT id, username, organization_id, role AS "role: UserRole", is_active, created_on, deactivated_on
+        FROM user_account
+        ORDER BY username
+        LIMIT $1 OFFSET $2"#,
+        limit,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = sqlx::query_scalar!(r#"SELECT count(*) AS "total!" FROM user_account"#)
+        .fetch_one(pool)
+        .await?;
+    Ok((users, total))
+}
+
+#[tracing::instrument(name = "fetch_user", skip(pool))]
+pub async fn fetch_user(pool: &PgPool, id: &Uuid) -> Result<Option<UserAccountModel>, anyhow::Error> {
+    let user = sqlx::query_as!(
+        UserAccountModel,
+        r#"SELECT id, username, organization_id, role AS "role: UserRole", is_active, created_on, deactivated_on
+        FROM user_account WHERE id = $1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(user)
+}
+
+#[tracing::instrument(name = "fetch_user_by_username", skip(pool))]
+pub async fn fetch_user_by_username(pool: &PgPool, username: &str) -> Result<Option<UserAccountModel>, anyhow::Error> {
+    let user = sqlx::query_as!(
+        UserAccountModel,
+        r#"SELECT id, username, organization_id, role AS "role: UserRole", is_active, created_on, deactivated_on
+        FROM user_account WHERE username = $1"#,
+        username
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(user)
+}
+
+/// Only the fields present in the request are changed. Reactivating clears `deactivated_on`.
+#[tracing::instrument(name = "update_user", skip(pool))]
+pub async fn update_user(
+    pool: &PgPool,
+    id: &Uuid,
+    req: &UpdateUserRequest,
+) -> Result<Option<UserAccountModel>, GenericError> {
+    sqlx::query_as!(
+        UserAccountModel,
+        r#"UPDATE user_account SET
+            username = COALESCE($2, username),
+            organization_id = COALESCE($3, organization_id),
+            is_active = COALESCE($4, is_active),
+            role = COALESCE($6, role),
+            deactivated_on = CASE
+                WHEN $4 IS NULL THEN deactivated_on
+                WHEN $4 THEN NULL
+                ELSE COALESCE(deactivated_on, $5)
+            END
+        WHERE id = $1
+        RETURNING id, username, organization_id, role AS "role: UserRole", is_active, created_on, deactivated_on"#,
+        id,
+        req.username,
+        req.organization_id,
+        req.is_active,
+        Utc::now(),
+        req.role as Option<UserRole>
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| map_user_write_error(e, req.username.as_deref()))
+}
+
+/// Removes the user, API keys go with them through the foreign key. Links owned by an
+/// organization stay with it, only links outside of any organization are removed.
+#[tracing::instrument(name = "delete_user", skip(pool))]
+pub async fn delete_user(pool: &PgPool, id: &Uuid) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    sqlx::query!("DELETE FROM short_url WHERE user_id = $1 AND organization_id IS NULL", id)
+        .execute(&mut *transaction)
+        .await?;
+    let result = sqlx::query!("DELETE FROM user_account WHERE id = $1", id)
+        .execute(&mut *transaction)
+        .await?;
+    transaction.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns the number of users and how many of them are active.
+#[tracing::instrument(name = "count_users", skip(pool))]
+pub async fn count_users(pool: &PgPool) -> Result<(i64, i64), anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT count(*) AS "total!", count(*) FILTER (WHERE is_active) AS "active!" FROM user_account"#
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((row.total, row.active))
+}
+
+/// `None` when the user does not exist, has been deactivated or revoked tokens of `token_version`.
+#[tracing::instrument(name = "fetch_authenticated_user", skip(pool))]
+pub async fn fetch_authenticated_user(
+    pool: &PgPool,
+    id: &Uuid,
+    token_version: i32,
+) -> Result<Option<AuthenticatedUser>, anyhow::Error> {
+    let user = sqlx::query_as!(
+        AuthenticatedUser,
+        r#"SELECT id, organization_id, role AS "role: UserRole"
+        FROM user_account WHERE id = $1 AND is_active AND token_version = $2"#,
+        id,
+        token_version
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(user)
+}
+
+/// Like `fetch_authenticated_user` for tokens of the external identity provider, which carry no token version.
+#[tracing::instrument(name = "fetch_authenticated_user_by_username", skip(pool))]
+pub async fn fetch_authenticated_user_by_username(
+    pool: &PgPool,
+    username: &str,
+) -> Result<Option<AuthenticatedUser>, anyhow::Error> {
+    let user = sqlx::query_as!(
+        AuthenticatedUser,
+        r#"SELECT id, organization_id, role AS "role: UserRole"
+        FROM user_account WHERE username = $1 AND is_active"#,
+        username
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(user)
+}
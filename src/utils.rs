@@ -1,51 +1,65 @@
 
-use std::{fs, io};
+use std::{collections::HashSet, fs, io, net::{IpAddr, SocketAddr}, path::Path};
 
-use chrono::{Duration, Utc};
+use actix_web::{http::header::{HeaderMap, X_FORWARDED_FOR}, HttpMessage, HttpRequest};
+use chrono::{DateTime, Duration, Utc};
 use config::{ConfigError, Environment};
+use futures::stream::{self, StreamExt};
 use rand::{distributions::Alphanumeric, Rng};
+use url::Url;
 use uuid::Uuid;
 
-use crate::{errors::CustomJWTTokenError, schemas::{DatabaseSettings, JWTClaims, Settings}};
-use secrecy::{ExposeSecret, SecretString};
-use sqlx::{Connection, Executor, PgConnection, PgPool};
-use jsonwebtoken::{
-    decode, encode, Algorithm as JWTAlgorithm, DecodingKey, EncodingKey, Header, Validation,
-};
+use crate::{destinations::DestinationResolver, domain_rules::DomainRuleSet, errors::{CustomJWTTokenError, GenericError, ShortUrlInsertError}, generators::{ShortUrlGenerator, ShortUrlGenerators}, jwt_keys::JwtKeys, models::{OrganizationModel, ShortUrlModel}, organizations::{apply_organization_settings, organization_code_length}, quotas::{check_link_quota, lock_link_usage}, schemas::{ApplicationSettings, AuthenticatedUser, BulkCreateMode, BulkCreateUrlRequest, CreateUrlRequest, DatabaseSettings, JWTClaims, ListUrlQuery, RedirectType, Settings, UpdateUrlRequest, UserRole}};
+use secrecy::SecretString;
+use sqlx::{Connection, Executor, PgConnection, PgExecutor, PgPool, Postgres, Transaction};
 
 #[tracing::instrument(name = "generate_short_url", skip())]
-pub fn generate_short_url() -> String {
+pub fn generate_short_url(length: usize) -> String {
     rand::thread_rng()
         .sample_iter(&Alphanumeric)
-        .take(6) 
+        .take(length)
         .map(char::from)
         .collect()
 }
 
+/// Paths served by the application itself that must never be handed out as aliases.
+const RESERVED_ALIASES: [&str; 11] = ["shorten", "docs", "api-docs", "links", "admin", "api-keys", "organization", "auth", "me", "health", "metrics"];
+const ALIAS_MIN_LENGTH: usize = 3;
+const ALIAS_MAX_LENGTH: usize = 64;
+
+#[tracing::instrument(name = "validate_alias")]
+pub fn validate_alias(alias: &str) -> Result<(), GenericError> {
+    if !(ALIAS_MIN_LENGTH..=ALIAS_MAX_LENGTH).contains(&alias.len()) {
+        return Err(GenericError::ValidationError(format!(
+            "Alias must be between {} and {} characters long",
+            ALIAS_MIN_LENGTH, ALIAS_MAX_LENGTH
+        )));
+    }
+    if !alias
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(GenericError::ValidationError(
+            "Alias may only contain letters, digits, '-' and '_'".to_string(),
+        ));
+    }
+    if RESERVED_ALIASES.contains(&alias.to_lowercase().as_str()) {
+        return Err(GenericError::ValidationError(format!(
+            "Alias '{}' is reserved",
+            alias
+        )));
+    }
+    Ok(())
+}
+
 
 
-#[tracing::instrument(name = "Decode JWT token")]
+#[tracing::instrument(name = "Decode JWT token", skip(keys))]
 pub fn decode_token<T: Into<String> + std::fmt::Debug>(
     token: T,
-    secret: &SecretString,
-) -> Result<Uuid, CustomJWTTokenError> {
-    let decoding_key = DecodingKey::from_secret(secret.expose_secret().as_bytes());
-    let decoded = decode::<JWTClaims>(
-        &token.into(),
-        &decoding_key,
-        &Validation::new(JWTAlgorithm::HS256),
-    );
-    match decoded {
-        Ok(token) => Ok(token.claims.sub),
-        Err(e) => {
-            match e.kind() {
-                jsonwebtoken::errors::ErrorKind::ExpiredSignature => {
-                    Err(CustomJWTTokenError::Expired)
-                }
-                _ => Err(CustomJWTTokenError::Invalid("Invalid Token".to_string())),
-            }
-        }
-    }
+    keys: &JwtKeys,
+) -> Result<JWTClaims, CustomJWTTokenError> {
+    keys.decode::<JWTClaims>(&token.into())
 }
 
 pub fn error_chain_fmt(
@@ -63,30 +77,410 @@ pub fn error_chain_fmt(
 
 
 
-#[tracing::instrument(name = "insert_url", skip(pool))]
+#[tracing::instrument(name = "insert_url", skip(executor))]
 pub async fn insert_url(
-    pool: &PgPool,
+    executor: impl PgExecutor<'_>,
     original_url: &str,
     short_url: &str,
     user_id: &Uuid,
-) -> Result<(), anyhow::Error> {
+    organization_id: Option<&Uuid>,
+    expiry_date: Option<DateTime<Utc>>,
+    redirect_type: Option<RedirectType>,
+) -> Result<(), ShortUrlInsertError> {
+    insert_url_with_id(executor, None, original_url, short_url, user_id, organization_id, expiry_date, redirect_type).await
+}
+
+/// Also logs the creation in `link_creation`, daily and monthly quotas count from there so deleting links doesn't free them.
+#[allow(clippy::too_many_arguments)]
+async fn insert_url_with_id(
+    executor: impl PgExecutor<'_>,
+    id: Option<i32>,
+    original_url: &str,
+    short_url: &str,
+    user_id: &Uuid,
+    organization_id: Option<&Uuid>,
+    expiry_date: Option<DateTime<Utc>>,
+    redirect_type: Option<RedirectType>,
+) -> Result<(), ShortUrlInsertError> {
     sqlx::query!(
         r#"
-        INSERT INTO short_url (original_url, short_url, created_on, user_id) 
-        VALUES ($1, $2, $3, $4)
+        WITH inserted AS (
+            INSERT INTO short_url (id, original_url, short_url, created_on, user_id, organization_id, expiry_date, redirect_type) 
+            VALUES (COALESCE($1, nextval(pg_get_serial_sequence('short_url', 'id'))::INT4), $2, $3, $4, $5, $8, $6, $7)
+            RETURNING id, user_id, organization_id, created_on
+        )
+        INSERT INTO link_creation (short_url_id, user_id, organization_id, created_on)
+        SELECT id, user_id, organization_id, created_on FROM inserted
         "#,
+        id,
         original_url,
         short_url,
         Utc::now(),
-        user_id
+        user_id,
+        expiry_date,
+        redirect_type as Option<RedirectType>,
+        organization_id
     )
-    .execute(pool)
+    .execute(executor)
     .await
-    .map_err(|e| anyhow::anyhow!("Database error: {:?}", e))?;
+    .map_err(|e| match e.as_database_error() {
+        Some(db_error) if db_error.is_unique_violation() => ShortUrlInsertError::AlreadyExists,
+        _ => ShortUrlInsertError::UnexpectedError(anyhow::anyhow!("Database error: {:?}", e)),
+    })?;
 
     Ok(())
 }
 
+#[tracing::instrument(name = "next_short_url_id", skip(executor))]
+pub async fn next_short_url_id(executor: impl PgExecutor<'_>) -> Result<i32, anyhow::Error> {
+    let id = sqlx::query_scalar!(
+        r#"SELECT nextval(pg_get_serial_sequence('short_url', 'id'))::INT4 AS "id!""#
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(id)
+}
+
+/// Number of consecutive collisions tolerated at one code length before it is grown by one character.
+const COLLISIONS_PER_LENGTH: u32 = 2;
+
+/// Each attempt runs in a savepoint, so a collision doesn't abort a transaction `conn` is in.
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "insert_generated_url", skip(conn, generator))]
+pub async fn insert_generated_url(
+    conn: &mut PgConnection,
+    generator: &dyn ShortUrlGenerator,
+    original_url: &str,
+    user_id: &Uuid,
+    organization_id: Option<&Uuid>,
+    expiry_date: Option<DateTime<Utc>>,
+    redirect_type: Option<RedirectType>,
+    length: usize,
+    retries: u32,
+) -> Result<String, ShortUrlInsertError> {
+    let mut attempt = 0;
+    loop {
+        let code_length = length + (attempt / COLLISIONS_PER_LENGTH) as usize;
+        let id = match generator.is_sequential() {
+            true => Some(next_short_url_id(&mut *conn).await?),
+            false => None,
+        };
+        let short_url = generator.generate(id.unwrap_or_default(), code_length)?;
+        let mut savepoint = conn.begin().await.map_err(anyhow::Error::from)?;
+        let result = insert_url_with_id(&mut *savepoint, id, original_url, &short_url, user_id, organization_id, expiry_date, redirect_type).await;
+        match result {
+            Ok(()) => savepoint.commit().await.map_err(anyhow::Error::from)?,
+            Err(_) => savepoint.rollback().await.map_err(anyhow::Error::from)?,
+        }
+        match result {
+            Ok(()) => return Ok(short_url),
+            Err(ShortUrlInsertError::AlreadyExists) if attempt < retries => {
+                attempt += 1;
+                tracing::warn!(
+                    short_url = %short_url,
+                    code_length,
+                    attempt,
+                    "Short URL collision, retrying with a new code"
+                );
+            }
+            Err(ShortUrlInsertError::AlreadyExists) => {
+                tracing::error!(
+                    code_length,
+                    attempts = attempt + 1,
+                    "Short URL collision retries exhausted"
+                );
+                return Err(ShortUrlInsertError::AlreadyExists);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Schemes that can execute code or read local files, rejected whatever the configuration says.
+const FORBIDDEN_URL_SCHEMES: [&str; 4] = ["javascript", "data", "file", "vbscript"];
+
+/// Parses a destination URL and returns it in canonical form: lowercase scheme and host,
+/// IDN hosts in punycode, default ports dropped and an empty fragment removed.
+#[tracing::instrument(name = "normalize_url")]
+pub fn normalize_url(original_url: &str, allowed_schemes: &[String]) -> Result<String, GenericError> {
+    let mut url = Url::parse(original_url.trim()).map_err(|e| match e {
+        url::ParseError::RelativeUrlWithoutBase => GenericError::ValidationError(
+            "URL must be absolute and include a scheme such as https://".to_string(),
+        ),
+        e => GenericError::ValidationError(format!("Invalid URL: {}", e)),
+    })?;
+    if FORBIDDEN_URL_SCHEMES.contains(&url.scheme())
+        || !allowed_schemes
+            .iter()
+            .any(|scheme| scheme.eq_ignore_ascii_case(url.scheme()))
+    {
+        return Err(GenericError::ValidationError(format!(
+            "URL scheme '{}' is not allowed",
+            url.scheme()
+        )));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(GenericError::ValidationError("URL must include a host".to_string()));
+    }
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err(GenericError::ValidationError(
+            "URL must not contain credentials".to_string(),
+        ));
+    }
+    if url.fragment() == Some("") {
+        url.set_fragment(None);
+    }
+    Ok(url.to_string())
+}
+
+pub fn check_destination_domain(url: &str, domain_rules: &DomainRuleSet) -> Result<(), GenericError> {
+    if domain_rules.is_url_permitted(url) {
+        Ok(())
+    } else {
+        Err(GenericError::ValidationError(
+            "Destination domain is not allowed".to_string(),
+        ))
+    }
+}
+
+#[tracing::instrument(name = "validate_create_url_request", skip(pool, application, domain_rules, resolver, organization))]
+pub async fn validate_create_url_request(
+    pool: &PgPool,
+    req: &mut CreateUrlRequest,
+    application: &ApplicationSettings,
+    domain_rules: &DomainRuleSet,
+    resolver: &DestinationResolver,
+    organization: &OrganizationModel,
+) -> Result<(), GenericError> {
+    let original_url = normalize_url(&req.original_url, &application.allowed_url_schemes)?;
+    if req.expiry_date.is_some_and(|expiry_date| expiry_date <= Utc::now()) {
+        return Err(GenericError::ValidationError("Expiry date must be in the future".to_string()));
+    }
+    if let Some(alias) = &req.alias {
+        validate_alias(alias)?;
+    }
+    // Last, resolving can mean requests to other hosts and is wasted on a request that fails anyway.
+    req.original_url = resolver.resolve(pool, &original_url).await?;
+    check_destination_domain(&req.original_url, domain_rules)?;
+    apply_organization_settings(req, organization)?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "next_short_url_ids", skip(transaction))]
+async fn next_short_url_ids(
+    transaction: &mut Transaction<'_, Postgres>,
+    count: usize,
+) -> Result<Vec<i32>, anyhow::Error> {
+    let ids = sqlx::query_scalar!(
+        r#"SELECT nextval(pg_get_serial_sequence('short_url', 'id'))::INT4 AS "id!"
+        FROM generate_series(1, $1)"#,
+        count as i32
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    Ok(ids)
+}
+
+/// Inserts and logs all rows in one statement, skipping codes that already exist, and returns the inserted codes.
+#[tracing::instrument(name = "insert_url_batch", skip(transaction, rows))]
+async fn insert_url_batch(
+    transaction: &mut Transaction<'_, Postgres>,
+    rows: &[(Option<i32>, &CreateUrlRequest, String)],
+    user_id: &Uuid,
+    organization_id: &Uuid,
+) -> Result<HashSet<String>, anyhow::Error> {
+    let ids: Vec<Option<i32>> = rows.iter().map(|(id, _, _)| *id).collect();
+    let original_urls: Vec<String> = rows.iter().map(|(_, req, _)| req.original_url.clone()).collect();
+    let short_urls: Vec<String> = rows.iter().map(|(_, _, short_url)| short_url.clone()).collect();
+    let expiry_dates: Vec<Option<DateTime<Utc>>> = rows.iter().map(|(_, req, _)| req.expiry_date).collect();
+    let redirect_types: Vec<Option<RedirectType>> = rows.iter().map(|(_, req, _)| req.redirect_type).collect();
+    let inserted = sqlx::query_scalar!(
+        r#"
+        WITH inserted AS (
+            INSERT INTO short_url (id, original_url, short_url, created_on, user_id, organization_id, expiry_date, redirect_type)
+            SELECT COALESCE(id, nextval(pg_get_serial_sequence('short_url', 'id'))::INT4), original_url, short_url, $4, $5, $8, expiry_date, redirect_type
+            FROM UNNEST($1::INT4[], $2::TEXT[], $3::TEXT[], $6::TIMESTAMPTZ[], $7::redirect_type[])
+                AS t(id, original_url, short_url, expiry_date, redirect_type)
+            ON CONFLICT (short_url) DO NOTHING
+            RETURNING id, short_url, user_id, organization_id, created_on
+        ), logged AS (
+            INSERT INTO link_creation (short_url_id, user_id, organization_id, created_on)
+            SELECT id, user_id, organization_id, created_on FROM inserted
+        )
+        SELECT short_url AS "short_url!" FROM inserted
+        "#,
+        &ids as &[Option<i32>],
+        &original_urls,
+        &short_urls,
+        Utc::now(),
+        user_id,
+        &expiry_dates as &[Option<DateTime<Utc>>],
+        &redirect_types as &[Option<RedirectType>],
+        organization_id
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+    Ok(inserted.into_iter().collect())
+}
+
+/// Bulk items validated at the same time.
+const BULK_VALIDATION_CONCURRENCY: usize = 16;
+
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(name = "insert_bulk_urls", skip(pool, generators, application, domain_rules, resolver, organization, quota_user, req))]
+pub async fn insert_bulk_urls(
+    pool: &PgPool,
+    generators: &ShortUrlGenerators,
+    application: &ApplicationSettings,
+    domain_rules: &DomainRuleSet,
+    resolver: &DestinationResolver,
+    user_id: &Uuid,
+    organization: &OrganizationModel,
+    quota_user: Option<&AuthenticatedUser>,
+    req: &mut BulkCreateUrlRequest,
+) -> Result<Vec<Result<String, GenericError>>, anyhow::Error> {
+    // Resolving short link destinations can take several network hops per item.
+    let mut results: Vec<Option<Result<String, GenericError>>> = stream::iter(req.urls.iter_mut())
+        .map(|url| async move {
+            if url.alias.is_none() {
+                if let Err(e) = generators.get(url.strategy.as_ref().unwrap_or(&application.short_url_strategy)) {
+                    return Some(Err(e));
+                }
+            }
+            validate_create_url_request(pool, url, application, domain_rules, resolver, organization)
+                .await
+                .err()
+                .map(Err)
+        })
+        .buffered(BULK_VALIDATION_CONCURRENCY)
+        .collect()
+        .await;
+    let mut transaction = pool.begin().await?;
+    // Valid items are accepted in order until the quota is used up, the rest fail with the quota error.
+    if let Some(user) = quota_user {
+        let usage = &lock_link_usage(&mut transaction, user).await?;
+        let mut accepted = 0;
+        for result in results.iter_mut().filter(|result| result.is_none()) {
+            match check_link_quota(usage, accepted + 1) {
+                Ok(()) => accepted += 1,
+                Err(e) => *result = Some(Err(e)),
+            }
+        }
+    }
+    let req = &*req;
+    if req.mode == BulkCreateMode::AllOrNothing && results.iter().any(Option::is_some) {
+        return Ok(finalize_bulk_results(results, true));
+    }
+
+    let length = organization_code_length(organization, application.short_url_length);
+    let mut attempt = 0;
+    loop {
+        let pending: Vec<usize> = (0..req.urls.len()).filter(|&i| results[i].is_none()).collect();
+        if pending.is_empty() {
+            break;
+        }
+        let code_length = length + (attempt / COLLISIONS_PER_LENGTH) as usize;
+        let strategy = |url: &CreateUrlRequest| url.strategy.clone().unwrap_or_else(|| application.short_url_strategy.clone());
+        // Only sequential codes are derived from the id, the other rows take theirs on insert.
+        let sequential = pending
+            .iter()
+            .filter(|&&index| req.urls[index].alias.is_none())
+            .filter(|&&index| generators.get(&strategy(&req.urls[index])).is_ok_and(|generator| generator.is_sequential()))
+            .count();
+        let mut ids = match sequential {
+            0 => vec![],
+            count => next_short_url_ids(&mut transaction, count).await?,
+        }
+        .into_iter();
+        let mut claimed = HashSet::new();
+        let mut rows = Vec::with_capacity(pending.len());
+        let mut row_indexes = Vec::with_capacity(pending.len());
+        for &index in &pending {
+            let url = &req.urls[index];
+            let (id, short_url) = match &url.alias {
+                Some(alias) => (None, alias.to_owned()),
+                None => {
+                    let generator = generators.get(&strategy(url))?;
+                    let id = match generator.is_sequential() {
+                        true => Some(ids.next().ok_or_else(|| anyhow::anyhow!("Too few short URL ids allocated"))?),
+                        false => None,
+                    };
+                    (id, generator.generate(id.unwrap_or_default(), code_length)?)
+                }
+            };
+            if !claimed.insert(short_url.clone()) {
+                if url.alias.is_some() {
+                    results[index] = Some(Err(GenericError::Conflict(format!(
+                        "Alias '{}' is already taken",
+                        short_url
+                    ))));
+                }
+                continue;
+            }
+            rows.push((id, url, short_url));
+            row_indexes.push(index);
+        }
+
+        let inserted = insert_url_batch(&mut transaction, &rows, user_id, &organization.id).await?;
+        let mut collisions = 0;
+        for (index, (_, url, short_url)) in row_indexes.into_iter().zip(rows) {
+            if inserted.contains(&short_url) {
+                results[index] = Some(Ok(short_url));
+            } else if url.alias.is_some() {
+                results[index] = Some(Err(GenericError::Conflict(format!(
+                    "Alias '{}' is already taken",
+                    short_url
+                ))));
+            } else {
+                collisions += 1;
+            }
+        }
+        let remaining = results.iter().filter(|result| result.is_none()).count();
+        if remaining == 0 {
+            break;
+        }
+        if attempt >= application.short_url_retries {
+            tracing::error!(remaining, code_length, "Short URL collision retries exhausted in bulk insert");
+            for result in results.iter_mut().filter(|result| result.is_none()) {
+                *result = Some(Err(GenericError::UnexpectedError(anyhow::anyhow!(
+                    "Could not generate a unique short URL"
+                ))));
+            }
+            break;
+        }
+        attempt += 1;
+        tracing::warn!(collisions, remaining, code_length, attempt, "Short URL collisions in bulk insert, retrying");
+    }
+
+    let failed = results.iter().any(|result| matches!(result, Some(Err(_))));
+    if req.mode == BulkCreateMode::AllOrNothing && failed {
+        transaction.rollback().await?;
+        return Ok(finalize_bulk_results(results, true));
+    }
+    transaction.commit().await?;
+    Ok(finalize_bulk_results(results, false))
+}
+
+/// Replaces the outcome of every item that did not fail on its own when the whole batch is rolled back.
+fn finalize_bulk_results(
+    results: Vec<Option<Result<String, GenericError>>>,
+    rolled_back: bool,
+) -> Vec<Result<String, GenericError>> {
+    results
+        .into_iter()
+        .map(|result| match result {
+            Some(Err(e)) => Err(e),
+            Some(Ok(_)) | None if rolled_back => Err(GenericError::ValidationError(
+                "Not created because another item in the batch failed".to_string(),
+            )),
+            Some(Ok(short_url)) => Ok(short_url),
+            None => Err(GenericError::UnexpectedError(anyhow::anyhow!(
+                "Short URL was not processed"
+            ))),
+        })
+        .collect()
+}
+
 pub async fn get_original_url(pool: &PgPool, short_url: &str) -> sqlx::Result<Option<String>> {
     let result = sqlx::query_scalar!(
         "SELECT original_url FROM short_url WHERE short_url = $1",
@@ -98,6 +492,185 @@ pub async fn get_original_url(pool: &PgPool, short_url: &str) -> sqlx::Result<Op
     Ok(result)
 }
 
+#[tracing::instrument(name = "fetch_short_url", skip(pool))]
+pub async fn fetch_short_url(pool: &PgPool, short_url: &str) -> sqlx::Result<Option<ShortUrlModel>> {
+    let result = sqlx::query_as!(
+        ShortUrlModel,
+        r#"SELECT id, short_url, original_url, created_on, user_id, organization_id, expiry_date,
+            redirect_type AS "redirect_type: RedirectType"
+        FROM short_url
+        WHERE short_url = $1
+            AND NOT EXISTS (SELECT 1 FROM user_account WHERE id = short_url.user_id AND NOT is_active)"#,
+        short_url
+    )
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result)
+}
+
+/// The `*_org_short_url(s)` queries are scoped to the links an organization owns, admins pass
+/// `None` to reach every link.
+#[tracing::instrument(name = "fetch_org_short_url", skip(pool))]
+pub async fn fetch_org_short_url(
+    pool: &PgPool,
+    short_url: &str,
+    organization_id: Option<&Uuid>,
+) -> sqlx::Result<Option<ShortUrlModel>> {
+    sqlx::query_as!(
+        ShortUrlModel,
+        r#"SELECT id, short_url, original_url, created_on, user_id, organization_id, expiry_date,
+            redirect_type AS "redirect_type: RedirectType"
+        FROM short_url WHERE short_url = $1 AND ($2::uuid IS NULL OR organization_id = $2)"#,
+        short_url,
+        organization_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(name = "list_org_short_urls", skip(pool))]
+pub async fn list_org_short_urls(
+    pool: &PgPool,
+    organization_id: Option<&Uuid>,
+    query: &ListUrlQuery,
+    limit: i64,
+    offset: i64,
+) -> sqlx::Result<(Vec<ShortUrlModel>, i64)> {
+    let urls = sqlx::query_as!(
+        ShortUrlModel,
+        r#"SELECT id, short_url, original_url, created_on, user_id, organization_id, expiry_date,
+            redirect_type AS "redirect_type: RedirectType"
+        FROM short_url
+        WHERE ($1::uuid IS NULL OR organization_id = $1)
+        AND ($2::TIMESTAMPTZ IS NULL OR created_on >= $2)
+        AND ($3::TIMESTAMPTZ IS NULL OR created_on <= $3)
+        AND ($4::TEXT IS NULL OR strpos(lower(original_url), lower($4)) > 0)
+        AND ($7::uuid IS NULL OR user_id = $7)
+        ORDER BY created_on DESC, id DESC
+        LIMIT $5 OFFSET $6"#,
+        organization_id,
+        query.created_from,
+        query.created_to,
+        query.search,
+        limit,
+        offset,
+        query.created_by
+    )
+    .fetch_all(pool)
+    .await?;
+    let total = sqlx::query_scalar!(
+        r#"SELECT count(*) AS "count!"
+        FROM short_url
+        WHERE ($1::uuid IS NULL OR organization_id = $1)
+        AND ($2::TIMESTAMPTZ IS NULL OR created_on >= $2)
+        AND ($3::TIMESTAMPTZ IS NULL OR created_on <= $3)
+        AND ($4::TEXT IS NULL OR strpos(lower(original_url), lower($4)) > 0)
+        AND ($5::uuid IS NULL OR user_id = $5)"#,
+        organization_id,
+        query.created_from,
+        query.created_to,
+        query.search,
+        query.created_by,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((urls, total))
+}
+
+#[tracing::instrument(name = "update_org_short_url", skip(pool))]
+pub async fn update_org_short_url(
+    pool: &PgPool,
+    short_url: &str,
+    organization_id: Option<&Uuid>,
+    req: &UpdateUrlRequest,
+) -> sqlx::Result<Option<ShortUrlModel>> {
+    sqlx::query_as!(
+        ShortUrlModel,
+        r#"UPDATE short_url
+        SET original_url = COALESCE($3, original_url),
+            expiry_date = CASE WHEN $4 THEN $5 ELSE expiry_date END,
+            redirect_type = COALESCE($6, redirect_type)
+        WHERE short_url = $1 AND ($2::uuid IS NULL OR organization_id = $2)
+        RETURNING id, short_url, original_url, created_on, user_id, organization_id, expiry_date,
+            redirect_type AS "redirect_type: RedirectType""#,
+        short_url,
+        organization_id,
+        req.original_url,
+        req.expiry_date.is_some(),
+        req.expiry_date.flatten(),
+        req.redirect_type as Option<RedirectType>
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(name = "delete_org_short_url", skip(pool))]
+pub async fn delete_org_short_url(pool: &PgPool, short_url: &str, organization_id: Option<&Uuid>) -> sqlx::Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM short_url WHERE short_url = $1 AND ($2::uuid IS NULL OR organization_id = $2)",
+        short_url,
+        organization_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns the number of links and how many of them have not expired.
+#[tracing::instrument(name = "count_short_urls", skip(pool))]
+pub async fn count_short_urls(pool: &PgPool) -> sqlx::Result<(i64, i64)> {
+    let row = sqlx::query!(
+        r#"SELECT count(*) AS "total!",
+            count(*) FILTER (WHERE expiry_date IS NULL OR expiry_date > now()) AS "active!"
+        FROM short_url"#
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((row.total, row.active))
+}
+
+pub fn get_request_user_id(request: &HttpRequest) -> Result<Uuid, GenericError> {
+    request
+        .extensions()
+        .get::<Uuid>()
+        .copied()
+        .ok_or_else(|| GenericError::ValidationError("User ID not found".to_string()))
+}
+
+pub fn get_request_user(request: &HttpRequest) -> Result<AuthenticatedUser, GenericError> {
+    request
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .copied()
+        .ok_or_else(|| GenericError::ValidationError("User not found".to_string()))
+}
+
+/// Address of the client behind `peer`. `X-Forwarded-For` is only read when `peer` is one of
+/// `trusted_proxies`, anyone else could put any address there. The header is walked from the
+/// right, skipping our own proxies, so the first other entry is the one our proxy saw.
+pub fn client_ip(peer: Option<SocketAddr>, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    let peer = peer?.ip();
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    let mut client = peer;
+    for addr in forwarded.iter().rev() {
+        let Ok(addr) = addr.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = addr;
+        if !trusted_proxies.contains(&addr) {
+            break;
+        }
+    }
+    Some(client)
+}
 
 pub fn get_configuration() -> Result<Settings, ConfigError> {
     let builder = config::Config::builder()
@@ -113,27 +686,112 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
     builder.try_deserialize::<Settings>()
 }
 
+const UNDEFINED_TABLE: &str = "42P01";
+const DUPLICATE_OBJECT: &str = "42710";
+const CREATE_SCHEMA_MIGRATION: &str =
+    "CREATE TABLE IF NOT EXISTS schema_migration (version INT PRIMARY KEY, applied_on TIMESTAMPTZ NOT NULL)";
+
+/// Number of the newest file in `migrations`, found by `build.rs`. Readiness fails until the database has caught up with it.
+pub const SCHEMA_VERSION: i32 = include!(concat!(env!("OUT_DIR"), "/schema_version.rs"));
+
+/// `014_migration.sql` is version 14.
+pub fn migration_version(path: &Path) -> Option<i32> {
+    path.file_name()?
+        .to_str()?
+        .split_once('_')?
+        .0
+        .parse()
+        .ok()
+}
+
+#[tracing::instrument(name = "Record migration version", skip(pool))]
+pub async fn record_migration_version(pool: &PgPool, version: i32) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        "INSERT INTO schema_migration (version, applied_on) VALUES ($1, $2) ON CONFLICT (version) DO NOTHING",
+        version,
+        Utc::now()
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Highest version up to which every migration was applied, `None` before `schema_migration` exists.
+/// Not instrumented, readiness probes call it every few seconds.
+pub async fn fetch_migration_version(pool: &PgPool) -> Result<Option<i32>, anyhow::Error> {
+    let Some(applied) = fetch_applied_migrations(pool).await? else {
+        return Ok(None);
+    };
+    Ok(Some((1..).take_while(|version| applied.contains(version)).last().unwrap_or(0)))
+}
+
+async fn fetch_applied_migrations(pool: &PgPool) -> Result<Option<HashSet<i32>>, anyhow::Error> {
+    match sqlx::query_scalar!("SELECT version FROM schema_migration").fetch_all(pool).await {
+        Ok(versions) => Ok(Some(versions.into_iter().collect())),
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(UNDEFINED_TABLE) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Files are skipped once their version is recorded, and only recorded when every statement in
+/// them succeeded, so a half-applied file is retried by the next run and keeps readiness failing.
 #[tracing::instrument(name = "Execute Queries")]
-async fn execute_query(path: &str, pool: &PgPool) -> io::Result<()> {
-    let migration_files = fs::read_dir(path)?;
-    for migration_file in migration_files {
-        let migration_file = migration_file?;
-        let migration_path = migration_file.path();
+pub(crate) async fn execute_query(path: &str, pool: &PgPool) -> io::Result<()> {
+    let mut migration_paths = fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, io::Error>>()?;
+    migration_paths.sort();
+    // Created ahead of `014_migration.sql` so the files before it are recorded on the first run too.
+    if let Err(err) = sqlx::query(CREATE_SCHEMA_MIGRATION).execute(pool).await {
+        eprintln!("Error creating schema_migration: {}", err);
+    }
+    let applied = fetch_applied_migrations(pool).await.unwrap_or_else(|err| {
+        eprintln!("Error fetching applied migrations: {}", err);
+        None
+    });
+    for migration_path in migration_paths {
+        let version = migration_version(&migration_path);
+        if version.is_some_and(|version| applied.as_ref().is_some_and(|applied| applied.contains(&version))) {
+            eprintln!("Migration already applied: {:?}", migration_path);
+            continue;
+        }
         let migration_sql = fs::read_to_string(&migration_path)?;
-        let statements: String = migration_sql.replace('\n', "");
+        // Comments are cut before the lines are joined, they would run to the end of the statement otherwise.
+        // A space, not nothing, or the last word of a line runs into the first of the next.
+        let statements = migration_sql
+            .lines()
+            .map(|line| line.split_once("--").map_or(line, |(code, _comment)| code))
+            .collect::<Vec<_>>()
+            .join(" ");
         let new_statement: Vec<&str> = statements
             .split(';')
-            .filter(|s| !s.trim().is_empty() & !s.starts_with("--"))
+            .filter(|s| !s.trim().is_empty())
             .collect();
+        let mut failed = false;
         for statement in new_statement {
-            if let Err(err) = sqlx::query(statement).execute(pool).await {
-                eprintln!("Error executing statement {:?}: {} ", statement, err);
-            } else {
-                eprintln!("Migration applied: {:?}", statement);
+            match sqlx::query(statement).execute(pool).await {
+                Ok(_) => eprintln!("Migration applied: {:?}", statement),
+                // `CREATE TYPE` has no `IF NOT EXISTS`, an existing type means it ran before.
+                Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(DUPLICATE_OBJECT) => {
+                    eprintln!("Migration already applied: {:?}", statement);
+                }
+                Err(err) => {
+                    failed = true;
+                    eprintln!("Error executing statement {:?}: {} ", statement, err);
+                }
             }
         }
 
+        if failed {
+            eprintln!("Migration failed, not recording it: {:?}", migration_path);
+            continue;
+        }
         eprintln!("Migration applied: {:?}", migration_path);
+        if let Some(version) = version {
+            if let Err(err) = record_migration_version(pool, version).await {
+                eprintln!("Error recording migration version {}: {}", version, err);
+            }
+        }
     }
 
     Ok(())
@@ -211,23 +869,25 @@ pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
 
 
 
-#[tracing::instrument(name = "Generate JWT token for user")]
+#[tracing::instrument(name = "Generate JWT token for user", skip(keys))]
 pub fn generate_jwt_token_for_user(
     user_id: Uuid,
-    expiry_time: i64,
-    secret: &SecretString,
+    role: UserRole,
+    token_version: i32,
+    expiry_secs: i64,
+    keys: &JwtKeys,
 ) -> Result<SecretString, anyhow::Error> {
     let expiration = Utc::now()
-        .checked_add_signed(Duration::hours(expiry_time))
+        .checked_add_signed(Duration::seconds(expiry_secs))
         .expect("valid timestamp")
         .timestamp() as usize;
     let claims: JWTClaims = JWTClaims {
         sub: user_id,
         exp: expiration,
+        role,
+        ver: token_version,
     };
-    let header = Header::new(JWTAlgorithm::HS256);
-    let encoding_key = EncodingKey::from_secret(secret.expose_secret().as_bytes());
-    let token: String = encode(&header, &claims, &encoding_key).expect("Failed to generate token");
+    let token: String = keys.encode(&claims)?;
     Ok(SecretString::new(token.into()))
 }
 